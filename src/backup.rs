@@ -0,0 +1,170 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::{BackupMode, Cli},
+    events::{self, Event},
+};
+
+/// the --backup-dir of a destination, relative directories are resolved against it
+pub fn backup_dir(cli: &Cli, destination: &Path) -> Option<PathBuf> {
+    cli.backup_dir.as_ref().map(|dir| destination.join(dir))
+}
+
+/// where a file in the destination `root` is moved to before it's replaced,
+/// or None without --backup
+pub fn backup_path(cli: &Cli, root: &Path, path: &Path) -> Option<PathBuf> {
+    let mode = cli.backup.as_ref()?;
+
+    let (base, relative_path) = match path.strip_prefix(root) {
+        Ok(relative_path) if relative_path != Path::new("") => (root, relative_path),
+        // a single file copied to the destination path itself
+        _ => (path.parent()?, Path::new(path.file_name()?)),
+    };
+
+    let path = match backup_dir(cli, base) {
+        Some(dir) => dir.join(relative_path),
+        None => base.join(relative_path),
+    };
+
+    let mut name = path.file_name()?.to_os_string();
+
+    match mode {
+        BackupMode::Simple => name.push(&cli.backup_suffix),
+        BackupMode::Numbered => name.push(format!(".~{}~", next_number(&path))),
+    }
+
+    Some(path.with_file_name(name))
+}
+
+/// move a file to its backup path. returns false without --backup
+pub fn back_up(cli: &Cli, root: &Path, path: &Path) -> std::io::Result<bool> {
+    let Some(backup) = backup_path(cli, root, path) else {
+        return Ok(false);
+    };
+
+    if let Some(parent) = backup.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::rename(path, &backup) {
+        Ok(()) => {}
+        // the backup directory can be on another filesystem
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            fs::copy(path, &backup)?;
+            fs::remove_file(path)?;
+        }
+        Err(e) => return Err(e),
+    }
+
+    events::emit(Event::Backup {
+        path,
+        backup: &backup,
+    });
+
+    Ok(true)
+}
+
+/// whether a file is named like a backup of the active --backup mode. such files are
+/// kept by --purge, since the backups made while copying aren't in the source
+pub fn is_backup(cli: &Cli, path: &Path) -> bool {
+    let (Some(mode), Some(name)) = (&cli.backup, path.file_name().and_then(|name| name.to_str()))
+    else {
+        return false;
+    };
+
+    match mode {
+        BackupMode::Simple => {
+            name.len() > cli.backup_suffix.len() && name.ends_with(&cli.backup_suffix)
+        }
+        BackupMode::Numbered => name
+            .strip_suffix('~')
+            .and_then(|name| name.rsplit_once(".~"))
+            .is_some_and(|(stem, number)| !stem.is_empty() && number.parse::<u64>().is_ok()),
+    }
+}
+
+/// one more than the highest number of the existing numbered backups of a file
+fn next_number(path: &Path) -> u64 {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return 1;
+    };
+
+    let mut prefix = OsString::from(name);
+    prefix.push(".~");
+    let prefix = prefix.to_string_lossy().into_owned();
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 1;
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix(&prefix)?
+                .strip_suffix('~')?
+                .parse::<u64>()
+                .ok()
+        })
+        .max()
+        .map_or(1, |number| number + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_backup_path() {
+        let dir = std::env::temp_dir().join(format!("pcp-backup-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/a.~1~"), "").unwrap();
+        fs::write(dir.join("sub/a.~3~"), "").unwrap();
+
+        let cli = |args: &[&str]| Cli::parse_from(["pcp"].iter().chain(args).chain(&["s", "d"]));
+        let file = dir.join("sub/a");
+
+        assert_eq!(backup_path(&cli(&[]), &dir, &file), None);
+        assert_eq!(
+            backup_path(&cli(&["--backup"]), &dir, &file),
+            Some(dir.join("sub/a~"))
+        );
+        assert_eq!(
+            backup_path(&cli(&["--backup=numbered"]), &dir, &file),
+            Some(dir.join("sub/a.~4~"))
+        );
+        assert_eq!(
+            backup_path(&cli(&["--backup", "--backup-dir", "old"]), &dir, &file),
+            Some(dir.join("old/sub/a~"))
+        );
+        // a single file is backed up next to itself
+        assert_eq!(
+            backup_path(&cli(&["--backup", "--backup-suffix", ".bak"]), &file, &file),
+            Some(dir.join("sub/a.bak"))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_backup() {
+        let cli = |args: &[&str]| Cli::parse_from(["pcp"].iter().chain(args).chain(&["s", "d"]));
+        let simple = cli(&["--backup"]);
+        let numbered = cli(&["--backup=numbered"]);
+
+        assert!(is_backup(&simple, Path::new("d/a.txt~")));
+        assert!(!is_backup(&simple, Path::new("d/a.txt")));
+        assert!(!is_backup(&simple, Path::new("d/~")));
+        assert!(is_backup(&numbered, Path::new("d/a.txt.~12~")));
+        assert!(!is_backup(&numbered, Path::new("d/a.txt~")));
+        assert!(!is_backup(&numbered, Path::new("d/a.~x~")));
+        assert!(!is_backup(&cli(&[]), Path::new("d/a.txt~")));
+    }
+}
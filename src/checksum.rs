@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+
+/// hashes of files by path, only loaded with `--overwrite checksum`
+static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cache {
+    files: HashMap<PathBuf, Entry>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+/// a hash is reused as long as the size and modification time of the file are unchanged
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    mtime: i64,
+    mtime_nanos: u32,
+    hash: String,
+}
+
+/// `$XDG_CACHE_HOME/pcp/checksums.json` on linux, the platform cache directory elsewhere
+pub fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("pcp").join("checksums.json"))
+}
+
+/// load the cache of a previous run. a missing or unreadable cache starts empty
+pub fn init() {
+    let cache = cache_path()
+        .and_then(|path| File::open(path).ok())
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+        .unwrap_or_default();
+
+    CACHE.get_or_init(|| Mutex::new(cache));
+}
+
+/// the blake3 hash of a file, from the cache if the file didn't change since it was hashed
+pub fn hash(path: &Path, metadata: &fs::Metadata) -> std::io::Result<String> {
+    let mtime = FileTime::from_last_modification_time(metadata);
+    let path = std::path::absolute(path)?;
+
+    if let Some(cache) = CACHE.get() {
+        let cache = cache.lock().expect("Failed to lock checksum cache");
+
+        if let Some(entry) = cache.files.get(&path).filter(|entry| {
+            entry.size == metadata.len()
+                && entry.mtime == mtime.unix_seconds()
+                && entry.mtime_nanos == mtime.nanoseconds()
+        }) {
+            return Ok(entry.hash.clone());
+        }
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(&path)?)?;
+    let hash = hasher.finalize().to_hex().to_string();
+
+    if let Some(cache) = CACHE.get() {
+        let mut cache = cache.lock().expect("Failed to lock checksum cache");
+
+        cache.files.insert(
+            path,
+            Entry {
+                size: metadata.len(),
+                mtime: mtime.unix_seconds(),
+                mtime_nanos: mtime.nanoseconds(),
+                hash: hash.clone(),
+            },
+        );
+        cache.changed = true;
+    }
+
+    Ok(hash)
+}
+
+/// forget a file which was rewritten, its new content is hashed on the next run
+pub fn invalidate(path: &Path) {
+    let (Some(cache), Ok(path)) = (CACHE.get(), std::path::absolute(path)) else {
+        return;
+    };
+
+    let mut cache = cache.lock().expect("Failed to lock checksum cache");
+
+    if cache.files.remove(&path).is_some() {
+        cache.changed = true;
+    }
+}
+
+/// write the cache back if anything was hashed. entries of files which
+/// no longer exist are dropped, so the cache doesn't grow forever
+pub fn save() -> std::io::Result<()> {
+    let (Some(cache), Some(path)) = (CACHE.get(), cache_path()) else {
+        return Ok(());
+    };
+
+    let mut cache = cache.lock().expect("Failed to lock checksum cache");
+
+    if !cache.changed {
+        return Ok(());
+    }
+
+    cache.files.retain(|path, _| path.is_file());
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // write to a temporary file first, so an interrupted save doesn't lose the cache.
+    // its name is unique to the process, since other jobs can save at the same time
+    let temp_path = path.with_extension(format!("json.{}.tmp", std::process::id()));
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    serde_json::to_writer(&mut writer, &*cache)?;
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(temp_path, path)?;
+    cache.changed = false;
+
+    Ok(())
+}
@@ -1,43 +1,224 @@
 use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 use std::{num::NonZeroUsize, str::FromStr};
 
 use clap::{Error, Parser, ValueEnum};
 
 #[derive(Debug, Clone, PartialEq, Parser)]
+#[command(args_override_self = true)]
 pub struct Cli {
-    /// the source directory to copy from
+    /// the source file or directory to copy from.
+    /// a trailing `/` copies the contents of the directory instead of the directory itself.
+    /// with --source this is the first destination
     pub source: Option<PathBuf>,
 
-    /// the destination directories to copy into
+    /// the destinations to copy to. existing directories are copied into, like cp.
+    /// with --target-directory these are more sources
     pub destinations: Vec<PathBuf>,
 
+    /// copy all positional arguments into this directory as sources, keeping their names
+    #[arg(short = 't', long)]
+    pub target_directory: Option<PathBuf>,
+
+    /// a source to copy. can be passed multiple times,
+    /// in which case all positional arguments are destinations
+    #[arg(long = "source")]
+    pub sources: Vec<PathBuf>,
+
+    /// what to do when multiple sources provide the same destination file
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::Error)]
+    pub on_conflict: ConflictPolicy,
+
     /// delete files in the destination directory
     /// that are not in the source directory
     #[arg(long, default_value = "false")]
     pub purge: bool,
 
+    /// turn off --purge, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "purge", hide = true)]
+    pub no_purge: bool,
+
+    /// only copy files matching this glob. can be passed multiple times.
+    /// a pattern ending in `/` includes everything in matching directories
+    #[arg(long)]
+    pub include: Vec<String>,
+
+    /// skip files and directories matching this glob, also when purging.
+    /// can be passed multiple times. patterns without a `/` match at any depth
+    #[arg(long)]
+    pub exclude: Vec<String>,
+
+    /// read exclude patterns from a file, one per line
+    #[arg(long)]
+    pub exclude_from: Vec<PathBuf>,
+
+    /// only copy files of at least this size
+    #[arg(long)]
+    pub min_size: Option<ByteSize>,
+
+    /// only copy files of at most this size
+    #[arg(long)]
+    pub max_size: Option<ByteSize>,
+
+    /// only copy files modified after this time. either a duration like `7d`
+    /// or a date like `2024-01-31` or `2024-01-31 12:00:00`, in UTC
+    #[arg(long, value_parser = parse_time)]
+    pub newer_than: Option<SystemTime>,
+
+    /// only copy files modified before this time, see --newer-than
+    #[arg(long, value_parser = parse_time)]
+    pub older_than: Option<SystemTime>,
+
+    /// only copy files of this type. can be passed multiple times
+    #[arg(long = "type", value_enum)]
+    pub file_types: Vec<FileType>,
+
+    /// don't descend into directories on other filesystems than the source,
+    /// such as mount points of network drives
+    #[arg(long)]
+    pub one_file_system: bool,
+
+    /// turn off --one-file-system, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "one_file_system", hide = true)]
+    pub no_one_file_system: bool,
+
+    /// only walk this many directory levels below the source
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+
+    /// skip files less than this many directory levels below the source.
+    /// files directly in the source are at depth 1
+    #[arg(long)]
+    pub min_depth: Option<usize>,
+
+    /// skip files ignored by .gitignore, .ignore and .pcpignore files
+    /// at any level of the source, also when purging
+    #[arg(long)]
+    pub use_ignore_files: bool,
+
+    /// turn off --use-ignore-files, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "use_ignore_files", hide = true)]
+    pub no_use_ignore_files: bool,
+
     /// if and when to overwrite existing files
     #[arg(long, value_enum, default_value_t = OverwriteMode::Never)]
     pub overwrite: OverwriteMode,
 
+    /// ask before overwriting existing files, same as --overwrite interactive
+    #[arg(short, long, overrides_with = "overwrite")]
+    pub interactive: bool,
+
+    /// turn off --interactive, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "interactive", hide = true)]
+    pub no_interactive: bool,
+
+    /// modification times closer than this are considered equal by the overwrite modes,
+    /// e.g. `2s` for filesystems with coarse timestamps like FAT
+    #[arg(long, default_value = "0s", value_parser = parse_duration)]
+    pub mtime_tolerance: Duration,
+
+    /// name of the incoming file when the destination exists with a different content
+    /// and --overwrite keep-both is used. `{stem}` is the name without the extension,
+    /// `{ext}` the extension including the dot and `{n}` counts up from 1
+    #[arg(long, default_value = "{stem} ({n}){ext}", value_parser = parse_keep_both_pattern)]
+    pub keep_both_pattern: String,
+
+    /// rename destination files aside before overwriting them.
+    /// `simple` appends --backup-suffix, `numbered` appends `.~1~`, `.~2~`, ...
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "simple"
+    )]
+    pub backup: Option<BackupMode>,
+
+    /// suffix of simple backups
+    #[arg(long, default_value = "~")]
+    pub backup_suffix: String,
+
+    /// put backups in this directory instead of next to the files, keeping their path
+    /// relative to the destination. a relative directory is relative to the destination
+    #[arg(long, requires = "backup")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// back up files removed by --purge instead of deleting them
+    #[arg(long, requires_all = ["purge", "backup"])]
+    pub backup_purged: bool,
+
+    /// turn off --backup-purged, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "backup_purged", hide = true)]
+    pub no_backup_purged: bool,
+
     /// move files instead of copying them.
     /// tries to use rename if possible.
     /// rename is not supported when passing multiple destinations
     #[arg(short = 'm', long = "move", default_value = "false")]
     pub move_files: bool,
 
+    /// turn off --move, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "move_files", hide = true)]
+    pub no_move: bool,
+
     /// limit the number of threads to use
-    #[arg(short, long)]
+    #[arg(short = 'j', long)]
     pub threads: Option<NonZeroUsize>,
 
+    /// limit the number of per-file progress bars shown at once.
+    /// defaults to the number of threads
+    #[arg(long)]
+    pub max_file_bars: Option<NonZeroUsize>,
+
+    /// how to display progress. `auto` uses bars when stderr is a terminal
+    /// and plain lines otherwise
+    #[arg(long, value_enum, default_value_t = ProgressMode::Auto)]
+    pub progress_mode: ProgressMode,
+
+    /// indicatif template for the per-file progress bars.
+    /// the message holds the source and destination paths
+    #[arg(long)]
+    pub bar_template: Option<String>,
+
+    /// indicatif template for the verification bars
+    #[arg(long)]
+    pub verify_template: Option<String>,
+
+    /// truncate paths in the middle so the bars fit on narrow terminals
+    #[arg(long)]
+    pub compact: bool,
+
+    /// turn off --compact, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "compact", hide = true)]
+    pub no_compact: bool,
+
+    /// don't use colors. the NO_COLOR environment variable is honored as well
+    #[arg(long)]
+    pub no_color: bool,
+
+    /// how often the job progress is printed in plain progress mode
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    pub plain_interval: Duration,
+
     /// set the buffer size for file operations
     #[arg(short, long, default_value = "8MiB")]
     pub buf_size: ByteSize,
 
+    /// how much of the files being copied to multiple destinations is held in memory,
+    /// shared by all files copied at the same time. also how far the fastest destination
+    /// can get ahead of the slowest one
+    #[arg(long, default_value = "64MiB")]
+    pub fan_out_buffer: ByteSize,
+
     /// display absolute paths
     #[arg(long)]
     pub absolute_paths: bool,
 
+    /// turn off --absolute-paths, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "absolute_paths", hide = true)]
+    pub no_absolute_paths: bool,
+
     /// track progress in a special .pcp/ directory which
     /// is removed once the job is done.
     /// if passed and the .pcp/ directory already exists, it will be
@@ -45,6 +226,72 @@ pub struct Cli {
     #[arg(long)]
     pub use_progress: bool,
 
+    /// turn off --use-progress, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "use_progress", hide = true)]
+    pub no_use_progress: bool,
+
+    /// how often the progress of a file is written to disk when using --use-progress.
+    /// either `chunk` to write after every chunk, a byte size like `64MiB`
+    /// or a duration like `5s`
+    #[arg(long, default_value = "chunk")]
+    pub checkpoint_interval: CheckpointInterval,
+
+    /// wait for other pcp jobs using --use-progress on the same destination
+    /// to finish instead of failing
+    #[arg(long)]
+    pub wait_for_lock: bool,
+
+    /// turn off --wait-for-lock, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "wait_for_lock", hide = true)]
+    pub no_wait_for_lock: bool,
+
+    /// write machine-readable json lines events to stdout instead of progress bars.
+    /// see json.md for the schema
+    #[arg(long)]
+    pub json: bool,
+
+    /// turn off --json, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "json", hide = true)]
+    pub no_json: bool,
+
+    /// write the json lines events to a file instead of stdout.
+    /// progress bars are only hidden when --json is passed as well
+    #[arg(long)]
+    pub json_file: Option<PathBuf>,
+
+    /// use the options of this profile from the config file
+    #[arg(long)]
+    pub profile: Option<String>,
+
+    /// read options from this config file instead of the default
+    /// `$XDG_CONFIG_HOME/pcp/config.toml`
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// don't read any config file
+    #[arg(long, conflicts_with_all = ["profile", "config"])]
+    pub no_config: bool,
+
+    /// print what would be done without touching the disk
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// turn off --dry-run, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "dry_run", hide = true)]
+    pub no_dry_run: bool,
+
+    /// print a line to stdout for every file describing what was done and why
+    #[arg(long, conflicts_with = "json")]
+    pub itemize: bool,
+
+    /// turn off --itemize, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "itemize", hide = true)]
+    pub no_itemize: bool,
+
+    /// write the itemized lines to a file instead of stdout
+    #[arg(long)]
+    pub itemize_file: Option<PathBuf>,
+
     #[clap(flatten)]
     pub verification: Verification,
 }
@@ -55,6 +302,10 @@ pub struct Verification {
     #[arg(long)]
     pub verify: bool,
 
+    /// turn off --verify, e.g. when it's enabled in the config
+    #[arg(long, overrides_with = "verify", hide = true)]
+    pub no_verify: bool,
+
     /// retry files which failed the hash check
     #[arg(long, default_value = "0")]
     pub verify_retries: u8,
@@ -65,6 +316,62 @@ pub enum OverwriteMode {
     Never,
     SizeDiffers,
     Always,
+    /// only when the source was modified after the destination
+    Newer,
+    /// when the size or the modification time differs
+    SizeOrMtimeDiffers,
+    /// when the content differs, compared by hashing both files if their sizes match
+    Checksum,
+    /// never, a file with a different content is saved under a new name
+    /// from --keep-both-pattern instead
+    KeepBoth,
+    /// ask for every existing file whether to overwrite, skip or rename it
+    Interactive,
+}
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum BackupMode {
+    /// `file~`, replacing an older backup
+    Simple,
+    /// `file.~1~`, `file.~2~`, ... keeping all backups
+    Numbered,
+}
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum ConflictPolicy {
+    /// fail before copying anything
+    Error,
+    /// the source passed first wins
+    First,
+    /// the source passed last wins
+    Last,
+}
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum FileType {
+    /// regular files
+    File,
+    /// symlinks to files
+    Symlink,
+    /// files with an executable bit set
+    Executable,
+    /// empty files
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckpointInterval {
+    EveryChunk,
+    Bytes(ByteSize),
+    Time(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, ValueEnum)]
+pub enum ProgressMode {
+    Auto,
+    Bars,
+    Plain,
+    None,
 }
 
 #[derive(Debug, Clone, Parser, PartialEq)]
@@ -136,3 +443,147 @@ impl FromStr for ByteUnit {
         }
     }
 }
+
+impl FromStr for CheckpointInterval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "chunk" {
+            return Ok(CheckpointInterval::EveryChunk);
+        }
+
+        if let Ok(duration) = parse_duration(s) {
+            return Ok(CheckpointInterval::Time(duration));
+        }
+
+        Ok(CheckpointInterval::Bytes(s.parse()?))
+    }
+}
+
+/// parse a duration like `500ms`, `10s`, `5m`, `2h` or `7d`
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let digits = s.chars().take_while(|c| c.is_ascii_digit()).count();
+
+    let value: u64 = s[..digits]
+        .parse()
+        .map_err(|_| Error::new(clap::error::ErrorKind::ValueValidation))?;
+
+    let duration = match &s[digits..] {
+        "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 60 * 60),
+        "d" => Duration::from_secs(value * 60 * 60 * 24),
+        _ => return Err(Error::new(clap::error::ErrorKind::ValueValidation)),
+    };
+
+    Ok(duration)
+}
+
+/// a --keep-both-pattern has to number the files and can't leave the directory
+pub fn parse_keep_both_pattern(s: &str) -> Result<String, Error> {
+    if !s.contains("{n}") || s.contains(std::path::is_separator) {
+        return Err(Error::new(clap::error::ErrorKind::ValueValidation));
+    }
+
+    Ok(s.to_string())
+}
+
+/// parse a point in time, either a duration ago like `7d` or an absolute
+/// date like `2024-01-31` or `2024-01-31 12:00:00` in UTC
+pub fn parse_time(s: &str) -> Result<SystemTime, Error> {
+    if let Ok(duration) = parse_duration(s) {
+        return SystemTime::now()
+            .checked_sub(duration)
+            .ok_or(Error::new(clap::error::ErrorKind::ValueValidation));
+    }
+
+    let timestamp = match s.len() {
+        10 => format!("{} 00:00:00", s),
+        _ => s.to_string(),
+    };
+
+    humantime::parse_rfc3339_weak(&timestamp)
+        .map_err(|_| Error::new(clap::error::ErrorKind::ValueValidation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        use clap::CommandFactory;
+
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_no_flags() {
+        // flags enabled by the config come first and can be turned off again
+        let cli = Cli::parse_from([
+            "pcp",
+            "--purge",
+            "--verify",
+            "-m",
+            "--no-purge",
+            "--no-verify",
+        ]);
+        assert!(!cli.purge);
+        assert!(!cli.verification.verify);
+        assert!(cli.move_files);
+
+        let cli = Cli::parse_from(["pcp", "--no-move", "-m"]);
+        assert!(cli.move_files);
+    }
+
+    #[test]
+    fn test_interactive_overrides_overwrite() {
+        // the config is passed before the command line, so the last one has to win
+        let cli = Cli::parse_from(["pcp", "--overwrite", "always", "-i", "s", "d"]);
+        assert!(cli.interactive);
+
+        let cli = Cli::parse_from(["pcp", "-i", "--overwrite", "always", "s", "d"]);
+        assert!(!cli.interactive);
+        assert_eq!(cli.overwrite, OverwriteMode::Always);
+    }
+
+    #[test]
+    fn test_parse_checkpoint_interval() {
+        assert_eq!(
+            "chunk".parse::<CheckpointInterval>().unwrap(),
+            CheckpointInterval::EveryChunk
+        );
+
+        assert_eq!(
+            "5s".parse::<CheckpointInterval>().unwrap(),
+            CheckpointInterval::Time(Duration::from_secs(5))
+        );
+
+        assert_eq!(
+            "64MiB".parse::<CheckpointInterval>().unwrap(),
+            CheckpointInterval::Bytes(ByteSize {
+                value: 64,
+                unit: ByteUnit::MiB
+            })
+        );
+
+        assert!("often".parse::<CheckpointInterval>().is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2024-01-31").unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1706659200)
+        );
+
+        assert_eq!(
+            parse_time("2024-01-31 00:00:10").unwrap(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1706659210)
+        );
+
+        assert!(parse_time("7d").unwrap() < SystemTime::now());
+        assert!(parse_time("last week").is_err());
+    }
+}
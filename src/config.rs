@@ -0,0 +1,175 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use clap::{CommandFactory, Parser};
+use toml::{Table, Value};
+
+use crate::{cli::Cli, summary};
+
+/// options which only make sense on the command line
+const CLI_ONLY: [&str; 3] = ["profile", "config", "no-config"];
+
+/// `$XDG_CONFIG_HOME/pcp/config.toml` on linux, the platform config directory elsewhere
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("pcp").join("config.toml"))
+}
+
+/// parse the command line again with the options of the config file in front of it,
+/// so options passed on the command line override the config
+pub fn apply(cli: Cli) -> std::io::Result<Cli> {
+    if cli.no_config {
+        return Ok(cli);
+    }
+
+    let Some(path) = cli.config.clone().or_else(default_path) else {
+        return Ok(cli);
+    };
+
+    // a missing default config is fine, unless it's needed for a profile
+    if !path.exists() && cli.config.is_none() && cli.profile.is_none() {
+        return Ok(cli);
+    }
+
+    let config = std::fs::read_to_string(&path)
+        .map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", path.display(), e),
+            )
+        })?
+        .parse::<Table>()
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid config {}: {}", path.display(), e),
+            )
+        })?;
+
+    let args = config_args(&config, cli.profile.as_deref(), &path)?;
+
+    let mut argv = std::env::args_os();
+    let argv = argv.next().into_iter().chain(args).chain(argv);
+
+    Ok(Cli::try_parse_from(argv).unwrap_or_else(|e| summary::exit_usage_error(e)))
+}
+
+/// the top level options merged with the ones of the profile, as command line arguments
+fn config_args(
+    config: &Table,
+    profile: Option<&str>,
+    path: &Path,
+) -> std::io::Result<Vec<OsString>> {
+    // options of the profile replace top level options of the same name
+    let mut options = BTreeMap::new();
+
+    for (key, value) in config.iter().filter(|(key, _)| *key != "profiles") {
+        options.insert(key.replace('_', "-"), (key, value));
+    }
+
+    if let Some(profile) = profile {
+        let table = config
+            .get("profiles")
+            .and_then(|profiles| profiles.get(profile))
+            .and_then(Value::as_table)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("Profile {} not found in {}", profile, path.display()),
+                )
+            })?;
+
+        for (key, value) in table {
+            options.insert(key.replace('_', "-"), (key, value));
+        }
+    }
+
+    let command = Cli::command();
+    let mut args = vec![];
+
+    for (name, (key, value)) in options {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(name.as_str()))
+            .filter(|_| !CLI_ONLY.contains(&name.as_str()))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Unknown option {} in {}", key, path.display()),
+                )
+            })?;
+
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+
+        for value in values {
+            let value = match value {
+                // flags are only passed when enabled
+                Value::Boolean(enabled) if !arg.get_action().takes_values() => {
+                    if *enabled {
+                        args.push(OsString::from(format!("--{}", name)));
+                    }
+
+                    continue;
+                }
+                Value::String(value) => value.clone(),
+                Value::Integer(_) | Value::Float(_) | Value::Boolean(_) | Value::Datetime(_) => {
+                    value.to_string()
+                }
+                Value::Array(_) | Value::Table(_) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Invalid value for {} in {}", key, path.display()),
+                    ))
+                }
+            };
+
+            args.push(OsString::from(format!("--{}={}", name, value)));
+        }
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_args() {
+        let config = r#"
+            verify = true
+            buf-size = "64MiB"
+            exclude = ["target/", "*.tmp"]
+
+            [profiles.backup]
+            verify_retries = 3
+            verify = false
+        "#
+        .parse::<Table>()
+        .unwrap();
+
+        let args = config_args(&config, Some("backup"), Path::new("config.toml")).unwrap();
+
+        assert_eq!(
+            args,
+            [
+                "--buf-size=64MiB",
+                "--exclude=target/",
+                "--exclude=*.tmp",
+                "--verify-retries=3",
+            ]
+            .map(OsString::from)
+        );
+
+        assert!(config_args(&config, Some("missing"), Path::new("config.toml")).is_err());
+
+        let config = "profile = \"backup\"".parse::<Table>().unwrap();
+        assert!(config_args(&config, None, Path::new("config.toml")).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backup::backup_path,
+    cli::Cli,
+    events::{self, DeleteReason, Event, PlanAction, SkipReason, WriteReason},
+    file_operations::{decide, purge_candidates, resumable, Decision},
+    filter::Filter,
+    progress::{is_completed, read_completed},
+    scan::Scan,
+};
+
+/// walk through all decisions for one source of a copy job and emit them as plan events,
+/// without creating or changing anything on disk
+pub fn plan(cli: &Cli, scan: &Scan, filter: &Filter) -> std::io::Result<()> {
+    let Scan {
+        source,
+        destinations,
+        files,
+        walked,
+    } = scan;
+
+    for destination in destinations {
+        // the .pcp/ state makes the destination exist, so renaming is never possible with it
+        if cli.move_files
+            && !cli.use_progress
+            && destinations.len() == 1
+            && filter.is_empty()
+            && files.len() == walked.len()
+            && can_rename(source, destination)
+        {
+            events::emit(Event::Plan {
+                src: Some(source),
+                dest: Some(destination),
+                size: 0,
+                action: PlanAction::Rename,
+            });
+
+            continue;
+        }
+
+        let state_root = scan.state_root(destination);
+        let completed = read_completed(state_root);
+
+        for file in files {
+            let Some(destination_path) = scan.target(destination, file) else {
+                continue;
+            };
+
+            let metadata = file.metadata()?;
+
+            let mut dest = destination_path.clone();
+
+            let action = if is_completed(&completed, state_root, &destination_path) {
+                PlanAction::Skip {
+                    reason: SkipReason::AlreadyCompleted,
+                }
+            } else {
+                let state_root = cli.use_progress.then_some(state_root);
+                let resumed = resumable(state_root, &destination_path);
+
+                match decide(cli, file.path(), &metadata, &destination_path, state_root)? {
+                    Decision::Write(WriteReason::New) => PlanAction::Copy,
+                    Decision::Ask => PlanAction::Ask,
+                    Decision::KeepBoth(renamed) => {
+                        dest = renamed;
+                        PlanAction::KeepBoth
+                    }
+                    Decision::Write(reason) => {
+                        if !resumed {
+                            let size = destination_path.metadata()?.len();
+                            plan_backup(cli, destination, &destination_path, size);
+                        }
+
+                        PlanAction::Overwrite { reason }
+                    }
+                    Decision::Skip(reason) => PlanAction::Skip { reason },
+                    Decision::MetadataUpdate => PlanAction::MetadataUpdate,
+                }
+            };
+
+            events::emit(Event::Plan {
+                src: Some(file.path()),
+                dest: Some(&dest),
+                size: metadata.len(),
+                action,
+            });
+
+            if cli.move_files {
+                events::emit(Event::Plan {
+                    src: Some(file.path()),
+                    dest: None,
+                    size: metadata.len(),
+                    action: PlanAction::Delete {
+                        reason: DeleteReason::Moved,
+                    },
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// emit the files --purge would delete from a destination, or back up with --backup-purged
+pub fn plan_purge(
+    cli: &Cli,
+    source: &Path,
+    destination: &Path,
+    targets: &HashSet<PathBuf>,
+    filter: &Filter,
+) {
+    for dest_file in purge_candidates(cli, source, destination, targets, filter) {
+        let size = dest_file.metadata().map(|m| m.len()).unwrap_or_default();
+
+        if cli.backup_purged {
+            plan_backup(cli, destination, dest_file.path(), size);
+            continue;
+        }
+
+        events::emit(Event::Plan {
+            src: None,
+            dest: Some(dest_file.path()),
+            size,
+            action: PlanAction::Delete {
+                reason: DeleteReason::Purged,
+            },
+        });
+    }
+}
+
+/// emit where a destination file would be backed up to, if --backup is set
+fn plan_backup(cli: &Cli, root: &Path, path: &Path, size: u64) {
+    if let Some(backup) = backup_path(cli, root, path) {
+        events::emit(Event::Plan {
+            src: Some(path),
+            dest: Some(&backup),
+            size,
+            action: PlanAction::Backup,
+        });
+    }
+}
+
+/// a move can only be done with a single rename if the destination doesn't exist yet
+/// and is on the same filesystem as the source
+#[cfg(unix)]
+fn can_rename(source: &Path, destination: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    if destination.exists() {
+        return false;
+    }
+
+    let (Some(parent), Ok(source_metadata)) = (destination.parent(), source.metadata()) else {
+        return false;
+    };
+
+    parent
+        .metadata()
+        .is_ok_and(|parent_metadata| parent_metadata.dev() == source_metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn can_rename(_source: &Path, _destination: &Path) -> bool {
+    // without a cheap way to tell, assume the files have to be copied
+    false
+}
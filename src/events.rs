@@ -0,0 +1,464 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, OnceLock},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::itemize;
+
+/// version of the json event schema. bumped on breaking changes, see json.md
+pub const SCHEMA_VERSION: u32 = 1;
+
+static SINK: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+static STATS: LazyLock<Mutex<Stats>> = LazyLock::new(Default::default);
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    JobStart {
+        schema: u32,
+        version: &'a str,
+    },
+    Scan {
+        source: &'a Path,
+        destinations: &'a [PathBuf],
+        files: u64,
+        bytes: u64,
+    },
+    FileStart {
+        src: &'a Path,
+        dest: &'a Path,
+        size: u64,
+        reason: WriteReason,
+        resumed_at: u64,
+    },
+    FileProgress {
+        src: &'a Path,
+        dest: &'a Path,
+        bytes: u64,
+        size: u64,
+    },
+    FileComplete {
+        src: &'a Path,
+        dest: &'a Path,
+        size: u64,
+    },
+    Skip {
+        src: &'a Path,
+        dest: &'a Path,
+        reason: SkipReason,
+    },
+    MetadataUpdate {
+        src: &'a Path,
+        dest: &'a Path,
+    },
+    Verify {
+        src: &'a Path,
+        dest: &'a Path,
+        ok: bool,
+    },
+    Retry {
+        src: &'a Path,
+        dest: &'a Path,
+    },
+    Rename {
+        src: &'a Path,
+        dest: &'a Path,
+    },
+    Delete {
+        path: &'a Path,
+        reason: DeleteReason,
+    },
+    Backup {
+        path: &'a Path,
+        backup: &'a Path,
+    },
+    Ignore {
+        path: &'a Path,
+    },
+    SkipMount {
+        path: &'a Path,
+    },
+    Error {
+        src: Option<&'a Path>,
+        dest: Option<&'a Path>,
+        message: String,
+    },
+    Plan {
+        src: Option<&'a Path>,
+        dest: Option<&'a Path>,
+        size: u64,
+        #[serde(flatten)]
+        action: PlanAction,
+    },
+    PlanSummary(PlanSummary),
+    JobSummary(Summary),
+}
+
+/// what a dry run would do with a file
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlanAction {
+    Copy,
+    Overwrite {
+        reason: WriteReason,
+    },
+    Skip {
+        reason: SkipReason,
+    },
+    MetadataUpdate,
+    Delete {
+        reason: DeleteReason,
+    },
+    Backup,
+    /// written under a new name, see `WriteReason::KeepBoth`
+    KeepBoth,
+    /// the destination exists and --overwrite interactive would ask what to do
+    Ask,
+    Rename,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PlanSummary {
+    pub copy: u64,
+    pub overwrite: u64,
+    pub skip: u64,
+    pub metadata_update: u64,
+    pub delete: u64,
+    pub backup: u64,
+    pub keep_both: u64,
+    pub ask: u64,
+    pub rename: u64,
+    /// files and directories skipped because of ignore files
+    pub ignore: u64,
+    /// directories not walked because of --one-file-system
+    pub skipped_mounts: Vec<PathBuf>,
+    /// bytes which would be written
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteReason {
+    /// the destination doesn't exist yet
+    New,
+    /// the destination has a different size and the overwrite mode is `size-differs`
+    SizeDiffers,
+    /// the overwrite mode is `always`
+    Always,
+    /// the source is newer than the destination and the overwrite mode is `newer`
+    Newer,
+    /// the modification time differs and the overwrite mode is `size-or-mtime-differs`
+    MtimeDiffers,
+    /// the content differs and the overwrite mode is `checksum`
+    ContentDiffers,
+    /// the destination exists with a different content, so the file is written under
+    /// a new name because the overwrite mode is `keep-both`
+    KeepBoth,
+    /// overwriting was confirmed and the overwrite mode is `interactive`
+    Confirmed,
+    /// the file was partially written by a previous run with --use-progress and is finished
+    Tracked,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// the destination exists and the overwrite mode is `never`
+    Exists,
+    /// the destination has the same size and the overwrite mode is `size-differs`
+    SameSize,
+    /// the source isn't newer than the destination and the overwrite mode is `newer`
+    NotNewer,
+    /// size and modification time are the same and the overwrite mode is `size-or-mtime-differs`
+    Unchanged,
+    /// the content is the same and the overwrite mode is `checksum` or `keep-both`
+    SameContent,
+    /// skipping was chosen and the overwrite mode is `interactive`
+    Declined,
+    /// the file was completed by a previous run according to the .pcp/ state
+    AlreadyCompleted,
+    /// another source provides the same destination file and wins by --on-conflict
+    Conflict,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteReason {
+    /// the file was removed from the destination by --purge
+    Purged,
+    /// the source file was removed after being moved
+    Moved,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Tally {
+    pub copied: u64,
+    pub skipped: u64,
+    pub already_completed: u64,
+    pub metadata_updated: u64,
+    pub failed: u64,
+    pub verified: u64,
+    pub verify_failed: u64,
+    pub retried: u64,
+    pub deleted: u64,
+    pub backed_up: u64,
+    /// files written under a new name by --overwrite keep-both
+    pub kept_both: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    #[serde(flatten)]
+    pub total: Tally,
+    /// files whose last verification failed, even after retrying
+    pub unresolved_verify_failures: u64,
+    /// files and directories skipped because of ignore files
+    pub ignored: u64,
+    /// directories not walked because of --one-file-system
+    pub skipped_mounts: Vec<PathBuf>,
+    pub elapsed_ms: u64,
+    pub bytes_per_sec: u64,
+    pub interrupted: bool,
+    pub destinations: Vec<DestinationSummary>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DestinationSummary {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub tally: Tally,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    ts: u64,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+#[derive(Default)]
+struct Stats {
+    total: Tally,
+    plan: PlanSummary,
+    destinations: Vec<(PathBuf, Tally)>,
+    verify_failures: HashSet<PathBuf>,
+    ignored: u64,
+    skipped_mounts: Vec<PathBuf>,
+}
+
+impl Tally {
+    fn record(&mut self, event: &Event) {
+        match event {
+            Event::FileComplete { size, .. } => {
+                self.copied += 1;
+                self.bytes += size;
+            }
+            Event::Skip {
+                reason: SkipReason::AlreadyCompleted,
+                ..
+            } => self.already_completed += 1,
+            Event::Skip { .. } => self.skipped += 1,
+            Event::MetadataUpdate { .. } => self.metadata_updated += 1,
+            Event::Verify { ok: true, .. } => self.verified += 1,
+            Event::Verify { ok: false, .. } => self.verify_failed += 1,
+            Event::Retry { .. } => self.retried += 1,
+            Event::Delete {
+                reason: DeleteReason::Purged,
+                ..
+            } => self.deleted += 1,
+            Event::Backup { .. } => self.backed_up += 1,
+            Event::FileStart {
+                reason: WriteReason::KeepBoth,
+                resumed_at: 0,
+                ..
+            } => self.kept_both += 1,
+            Event::Error { .. } => self.failed += 1,
+            _ => {}
+        }
+    }
+}
+
+impl PlanSummary {
+    fn record(&mut self, size: u64, action: &PlanAction) {
+        let counter = match action {
+            PlanAction::Copy => &mut self.copy,
+            PlanAction::Overwrite { .. } => &mut self.overwrite,
+            PlanAction::Skip { .. } => &mut self.skip,
+            PlanAction::MetadataUpdate => &mut self.metadata_update,
+            PlanAction::Delete { .. } => &mut self.delete,
+            PlanAction::Backup => &mut self.backup,
+            PlanAction::KeepBoth => &mut self.keep_both,
+            PlanAction::Ask => &mut self.ask,
+            PlanAction::Rename => &mut self.rename,
+        };
+
+        *counter += 1;
+
+        if let PlanAction::Copy | PlanAction::Overwrite { .. } | PlanAction::KeepBoth = action {
+            self.bytes += size;
+        }
+    }
+}
+
+impl Stats {
+    fn record(&mut self, event: &Event) {
+        match event {
+            Event::Scan { destinations, .. } => {
+                for destination in destinations.iter() {
+                    if !self.destinations.iter().any(|(d, _)| d == destination) {
+                        self.destinations
+                            .push((destination.clone(), Tally::default()));
+                    }
+                }
+            }
+            Event::Verify {
+                dest, ok: false, ..
+            } => {
+                self.verify_failures.insert(dest.to_path_buf());
+            }
+            Event::FileComplete { dest, .. } => {
+                self.verify_failures.remove(*dest);
+            }
+            Event::Plan { size, action, .. } => self.plan.record(*size, action),
+            Event::Ignore { .. } => {
+                self.ignored += 1;
+                self.plan.ignore += 1;
+            }
+            Event::SkipMount { path } => {
+                self.skipped_mounts.push(path.to_path_buf());
+                self.plan.skipped_mounts.push(path.to_path_buf());
+            }
+            _ => {}
+        }
+
+        self.total.record(event);
+
+        let Some(path) = event.path() else {
+            return;
+        };
+
+        // nested destinations are attributed to the innermost one
+        let destination = self
+            .destinations
+            .iter_mut()
+            .filter(|(destination, _)| path.starts_with(destination))
+            .max_by_key(|(destination, _)| destination.components().count());
+
+        if let Some((_, tally)) = destination {
+            tally.record(event);
+        }
+    }
+}
+
+impl Event<'_> {
+    /// the destination side path an event is about
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Event::FileStart { dest, .. }
+            | Event::FileProgress { dest, .. }
+            | Event::FileComplete { dest, .. }
+            | Event::Skip { dest, .. }
+            | Event::MetadataUpdate { dest, .. }
+            | Event::Verify { dest, .. }
+            | Event::Retry { dest, .. } => Some(dest),
+            Event::Delete { path, .. } | Event::Backup { path, .. } => Some(path),
+            Event::Error { dest, .. } => *dest,
+            _ => None,
+        }
+    }
+}
+
+/// start the job clock and optionally write all events as json lines.
+/// events go to the given file, or stdout if no file is given.
+pub fn init(json: bool, json_file: Option<&Path>) -> std::io::Result<()> {
+    STARTED.get_or_init(Instant::now);
+
+    if !json && json_file.is_none() {
+        return Ok(());
+    }
+
+    let writer: Box<dyn Write + Send> = match json_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout()),
+    };
+
+    _ = SINK.set(Mutex::new(writer));
+
+    emit(Event::JobStart {
+        schema: SCHEMA_VERSION,
+        version: env!("CARGO_PKG_VERSION"),
+    });
+
+    Ok(())
+}
+
+pub fn json_enabled() -> bool {
+    SINK.get().is_some()
+}
+
+pub fn emit(event: Event) {
+    STATS.lock().expect("Failed to lock stats").record(&event);
+    itemize::record(&event);
+
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+
+    let Ok(line) = serde_json::to_string(&Record { ts, event: &event }) else {
+        return;
+    };
+
+    let mut sink = sink.lock().expect("Failed to lock event sink");
+
+    // a closed pipe shouldn't take the copy job down with it
+    _ = writeln!(sink, "{}", line).and_then(|_| sink.flush());
+}
+
+pub fn plan_summary() -> PlanSummary {
+    STATS.lock().expect("Failed to lock stats").plan.clone()
+}
+
+pub fn summary(interrupted: bool) -> Summary {
+    let stats = STATS.lock().expect("Failed to lock stats");
+
+    let elapsed = STARTED
+        .get()
+        .map(|started| started.elapsed())
+        .unwrap_or_default();
+
+    let bytes_per_sec = match elapsed.as_secs_f64() {
+        secs if secs > 0.0 => (stats.total.bytes as f64 / secs) as u64,
+        _ => 0,
+    };
+
+    Summary {
+        total: stats.total.clone(),
+        unresolved_verify_failures: stats.verify_failures.len() as u64,
+        ignored: stats.ignored,
+        skipped_mounts: stats.skipped_mounts.clone(),
+        elapsed_ms: elapsed.as_millis() as u64,
+        bytes_per_sec,
+        interrupted,
+        destinations: stats
+            .destinations
+            .iter()
+            .map(|(path, tally)| DestinationSummary {
+                path: path.clone(),
+                tally: tally.clone(),
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,810 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs::{self, File},
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use filetime::{set_file_times, FileTime};
+use indicatif::HumanBytes;
+use rayon::prelude::*;
+use walkdir::DirEntry;
+
+use crate::{
+    cli::Cli,
+    events::{self, DeleteReason, Event, WriteReason},
+    file_operations::{
+        copy_file, delete_file, display_paths, open_destination, prepare_write, report_copy_error,
+        resumable, retry_cli, Outcome, Prepared, PROGRESS_EVENT_INTERVAL,
+    },
+    progress::{is_completed, CompletionTracker},
+    progress_bar::{FileBar, JobProgress},
+    signals,
+};
+
+/// a destination of a job which copies to several destinations at once
+pub struct FanOutDestination<'a> {
+    pub root: &'a Path,
+    pub tracker: &'a CompletionTracker,
+    /// files completed by a previous run, which are skipped
+    pub completed: &'a HashSet<OsString>,
+}
+
+/// a chunk of the source and the offset it starts at
+type Chunk = (u64, Arc<Buffered>);
+
+/// the chunks held in memory by all files copied to multiple destinations at the same time.
+/// every file gets its share of --fan-out-buffer from here, so the memory used doesn't
+/// grow with the number of threads
+static BUDGET: OnceLock<Budget> = OnceLock::new();
+
+struct Budget {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Budget {
+    /// wait until a chunk can be read
+    fn acquire(&'static self) -> Permit {
+        let mut available = self.available.lock().expect("Failed to lock budget");
+
+        while *available == 0 {
+            available = self
+                .released
+                .wait(available)
+                .expect("Failed to lock budget");
+        }
+
+        *available -= 1;
+        Permit(self)
+    }
+}
+
+/// a chunk in memory, returned to the budget when it's dropped
+struct Permit(&'static Budget);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.0.available.lock().expect("Failed to lock budget") += 1;
+        self.0.released.notify_one();
+    }
+}
+
+/// the data of a chunk, which is freed once every writer wrote it
+struct Buffered {
+    data: Box<[u8]>,
+    _permit: Permit,
+}
+
+impl Deref for Buffered {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+fn budget(cli: &Cli) -> &'static Budget {
+    BUDGET.get_or_init(|| Budget {
+        available: Mutex::new(chunks_per_buffer(cli)),
+        released: Condvar::new(),
+    })
+}
+
+/// how many chunks of --buf-size fit into --fan-out-buffer, at least one
+fn chunks_per_buffer(cli: &Cli) -> usize {
+    (cli.fan_out_buffer.to_bytes() / cli.buf_size.to_bytes()).max(1)
+}
+
+/// a destination file, written by its own thread
+struct Writer<'a> {
+    destination: &'a FanOutDestination<'a>,
+    /// the path the source maps to in the destination
+    target: PathBuf,
+    /// the path written to, which differs from the target with --overwrite keep-both
+    path: PathBuf,
+    reason: WriteReason,
+    file: File,
+    progress_bar: FileBar,
+    /// bytes of the file which are written already, including ones of a previous run
+    position: u64,
+}
+
+/// copy the files of a source to all destinations at once. each file is read only once
+/// and written to all destinations in parallel, a slow destination only holds back
+/// the others once it's --fan-out-buffer behind
+pub fn fan_out_files_par(
+    cli: &Cli,
+    source: &Path,
+    destinations: &[FanOutDestination],
+    files: &[DirEntry],
+    job_progress: &JobProgress,
+) {
+    files.par_iter().for_each(|entry| {
+        let src = entry.path();
+
+        let Ok(relative_path) = src.strip_prefix(source) else {
+            eprintln!("Error: Unable to get relative path");
+            return;
+        };
+
+        let targets = destinations
+            .iter()
+            .map(|destination| {
+                let target = if relative_path == Path::new("") {
+                    destination.root.to_path_buf()
+                } else {
+                    destination.root.join(relative_path)
+                };
+
+                (destination, target)
+            })
+            .filter(|(destination, target)| {
+                !destination
+                    .tracker
+                    .root()
+                    .is_some_and(|root| is_completed(destination.completed, root, target))
+            })
+            .collect::<Vec<_>>();
+
+        if targets.is_empty() {
+            return;
+        }
+
+        signals::wait_while_paused(None);
+
+        if signals::interrupted() {
+            for (_, target) in &targets {
+                signals::mark_remaining(src, target);
+            }
+
+            return;
+        }
+
+        if fan_out_file(cli, src, &targets, job_progress) && cli.move_files {
+            delete_file(src, DeleteReason::Moved);
+        }
+    });
+}
+
+/// copy one file to all of its targets. returns whether it arrived in all of them
+fn fan_out_file(
+    cli: &Cli,
+    src: &Path,
+    targets: &[(&FanOutDestination, PathBuf)],
+    job_progress: &JobProgress,
+) -> bool {
+    let opened = File::open(src).and_then(|file| {
+        let metadata = file.metadata()?;
+        Ok((file, metadata))
+    });
+
+    let (mut src_file, metadata) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            for (_, target) in targets {
+                report_copy_error(src, target, &e, job_progress);
+            }
+
+            return false;
+        }
+    };
+
+    let mut arrived = true;
+    let mut writers = vec![];
+
+    for (destination, target) in targets {
+        let opened = match prepare(cli, src, &metadata, destination, target, job_progress) {
+            Ok(Prepared::Write(path, reason)) => Writer::open(
+                cli,
+                src,
+                &metadata,
+                destination,
+                target,
+                path,
+                reason,
+                job_progress,
+            ),
+            Ok(Prepared::Done(outcome)) => {
+                // a declined or otherwise skipped file isn't in the destination
+                arrived &= outcome != Outcome::Skipped;
+                continue;
+            }
+            Err(e) => Err(e),
+        };
+
+        match opened {
+            Ok(writer) => writers.push(writer),
+            Err(e) => {
+                fail(src, target, &e, job_progress);
+                arrived = false;
+            }
+        }
+    }
+
+    if writers.is_empty() {
+        return arrived;
+    }
+
+    let (src_hash, results) = stream(cli, src, &mut src_file, &metadata, writers, job_progress);
+
+    // hash the source again if it was only read from where a previous run stopped
+    let src_hash = match (cli.verification.verify, src_hash) {
+        (false, _) => None,
+        (true, Some(hash)) => Some(Ok(hash)),
+        (true, None) => Some(hash_file(cli, src, None)),
+    };
+
+    let verified = std::thread::scope(|scope| {
+        results
+            .into_iter()
+            .map(|(writer, result)| {
+                let src_hash = src_hash.as_ref();
+
+                scope.spawn(move || {
+                    let verified = match (result, src_hash) {
+                        (Ok(()), Some(Ok(src_hash))) => {
+                            writer.verify(cli, src, src_hash, job_progress)
+                        }
+                        (Ok(()), Some(Err(e))) => Err(Error::new(e.kind(), e.to_string())),
+                        (Ok(()), None) => Ok(true),
+                        (Err(e), _) => Err(e),
+                    };
+
+                    (writer, verified)
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().expect("Failed to join verification"))
+            .collect::<Vec<_>>()
+    });
+
+    for (writer, verified) in verified {
+        arrived &= writer.finish(cli, src, &metadata, verified, job_progress);
+    }
+
+    arrived
+}
+
+/// read the source once and stream it to all writers, each in its own thread.
+/// with --verify the hash of the source is returned, if it was read from the start
+fn stream<'a>(
+    cli: &Cli,
+    src: &Path,
+    src_file: &mut File,
+    metadata: &fs::Metadata,
+    writers: Vec<Writer<'a>>,
+    job_progress: &JobProgress,
+) -> (Option<blake3::Hash>, Vec<(Writer<'a>, std::io::Result<()>)>) {
+    let start = writers.iter().map(|writer| writer.position).min();
+    let start = start.unwrap_or_default();
+
+    let capacity = chunks_per_buffer(cli);
+    let mut hasher = (cli.verification.verify && start == 0).then(blake3::Hasher::new);
+
+    std::thread::scope(|scope| {
+        let (senders, handles): (Vec<_>, Vec<_>) = writers
+            .into_iter()
+            .map(|mut writer| {
+                let (sender, receiver) = mpsc::sync_channel(capacity);
+
+                let handle = scope.spawn(move || {
+                    let result = writer.write(src, metadata, receiver, job_progress);
+                    (writer, result)
+                });
+
+                (sender, handle)
+            })
+            .unzip();
+
+        let read = read_chunks(
+            cli,
+            src_file,
+            start,
+            metadata.len(),
+            senders,
+            hasher.as_mut(),
+        );
+
+        let results = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Failed to join writer"))
+            .map(|(writer, result)| match &read {
+                // the writers only saw the source end early
+                Err(e) => (writer, Err(Error::new(e.kind(), e.to_string()))),
+                Ok(()) => (writer, result),
+            })
+            .collect();
+
+        let src_hash = match read {
+            Ok(()) => hasher.map(|hasher| hasher.finalize()),
+            Err(_) => None,
+        };
+
+        (src_hash, results)
+    })
+}
+
+/// send the source from `start` on to all writers which are still receiving
+fn read_chunks(
+    cli: &Cli,
+    src_file: &mut File,
+    start: u64,
+    total_size: u64,
+    mut senders: Vec<SyncSender<Chunk>>,
+    mut hasher: Option<&mut blake3::Hasher>,
+) -> std::io::Result<()> {
+    src_file.seek(SeekFrom::Start(start))?;
+
+    let budget = budget(cli);
+    let mut buffer = vec![0; cli.buf_size.to_bytes()];
+    let mut offset = start;
+
+    while offset < total_size && !senders.is_empty() {
+        // without progress tracking a partial file can't be resumed,
+        // so in that case the file is finished before stopping
+        if cli.use_progress && signals::interrupted() {
+            break;
+        }
+
+        signals::wait_while_paused(None);
+
+        // waits for the writers of this or other files to catch up
+        let permit = budget.acquire();
+        let bytes_read = src_file.read(&mut buffer)?;
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        let chunk = Arc::new(Buffered {
+            data: Box::from(&buffer[..bytes_read]),
+            _permit: permit,
+        });
+
+        if let Some(hasher) = hasher.as_mut() {
+            hasher.update(&chunk);
+        }
+
+        // a writer which failed stopped receiving
+        senders.retain(|sender| sender.send((offset, chunk.clone())).is_ok());
+        offset += bytes_read as u64;
+    }
+
+    Ok(())
+}
+
+/// apply the overwrite mode to a target of a file
+fn prepare(
+    cli: &Cli,
+    src: &Path,
+    metadata: &fs::Metadata,
+    destination: &FanOutDestination,
+    target: &Path,
+    job_progress: &JobProgress,
+) -> std::io::Result<Prepared> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    prepare_write(
+        cli,
+        src,
+        metadata,
+        target,
+        destination.root,
+        destination.tracker,
+        job_progress,
+    )
+}
+
+impl<'a> Writer<'a> {
+    /// open the file to write, `path` differs from the target with --overwrite keep-both
+    fn open(
+        cli: &Cli,
+        src: &Path,
+        metadata: &fs::Metadata,
+        destination: &'a FanOutDestination<'a>,
+        target: &Path,
+        path: PathBuf,
+        reason: WriteReason,
+        job_progress: &JobProgress,
+    ) -> std::io::Result<Self> {
+        let total_size = metadata.len();
+        let resume = resumable(destination.tracker.root(), &path);
+        let mut file = open_destination(&path, resume)?;
+
+        let progress_bar = job_progress.progress_bar(total_size);
+        let (src_str, dest_str) = display_paths(cli, src, &path);
+        progress_bar.set_message(job_progress.file_message(src_str, dest_str));
+
+        let mut position = 0;
+
+        if let Some(progress) = destination.tracker.add_progress_file(&path, total_size)? {
+            file.seek(SeekFrom::Start(progress.current))?;
+            progress_bar.set_position(progress.current);
+            job_progress.inc_bytes(progress.current);
+            position = progress.current;
+        }
+
+        events::emit(Event::FileStart {
+            src,
+            dest: &path,
+            size: total_size,
+            reason,
+            resumed_at: position,
+        });
+
+        Ok(Writer {
+            destination,
+            target: target.to_path_buf(),
+            path,
+            reason,
+            file,
+            progress_bar,
+            position,
+        })
+    }
+
+    fn write(
+        &mut self,
+        src: &Path,
+        metadata: &fs::Metadata,
+        chunks: Receiver<Chunk>,
+        job_progress: &JobProgress,
+    ) -> std::io::Result<()> {
+        let total_size = metadata.len();
+        let tracker = self.destination.tracker;
+
+        let mut last_progress_event = Instant::now();
+
+        for (offset, chunk) in chunks {
+            // the start of the file can be written by a previous run already
+            let skip = self.position.saturating_sub(offset) as usize;
+
+            let Some(chunk) = chunk.get(skip..).filter(|chunk| !chunk.is_empty()) else {
+                continue;
+            };
+
+            self.file.write_all(chunk)?;
+            self.position += chunk.len() as u64;
+            tracker.write_progress(&self.path, self.position)?;
+            self.progress_bar.set_position(self.position);
+            job_progress.inc_bytes(chunk.len() as u64);
+
+            if events::json_enabled() && last_progress_event.elapsed() >= PROGRESS_EVENT_INTERVAL {
+                last_progress_event = Instant::now();
+
+                events::emit(Event::FileProgress {
+                    src,
+                    dest: &self.path,
+                    bytes: self.position,
+                    size: total_size,
+                });
+            }
+        }
+
+        if self.position < total_size {
+            if !tracker.is_enabled() || !signals::interrupted() {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Source ended before it was written completely",
+                ));
+            }
+
+            tracker.flush_progress(&self.path, self.position)?;
+
+            self.progress_bar.abandon_with_message(format!(
+                "interrupted at {} of {} bytes",
+                self.position, total_size
+            ));
+
+            return Err(Error::new(ErrorKind::Interrupted, "Interrupted by signal"));
+        }
+
+        self.progress_bar.finish();
+
+        set_file_times(
+            &self.path,
+            FileTime::from_system_time(metadata.accessed()?),
+            FileTime::from_system_time(metadata.modified()?),
+        )?;
+
+        tracker.remove_progress_file(&self.path)
+    }
+
+    /// compare the hash of the written file with the one of the source
+    fn verify(
+        &self,
+        cli: &Cli,
+        src: &Path,
+        src_hash: &blake3::Hash,
+        job_progress: &JobProgress,
+    ) -> std::io::Result<bool> {
+        let verify_bar = job_progress.verify_bar(self.position);
+        let (src_str, dest_str) = display_paths(cli, src, &self.path);
+        verify_bar.set_message(job_progress.file_message(src_str, dest_str));
+
+        let ok = hash_file(cli, &self.path, Some(&verify_bar))? == *src_hash;
+
+        events::emit(Event::Verify {
+            src,
+            dest: &self.path,
+            ok,
+        });
+
+        if !ok {
+            eprintln!("  Verification failed for {}", dest_str);
+        }
+
+        verify_bar.finish();
+        Ok(ok)
+    }
+
+    /// report the outcome of the copy and retry a failed verification.
+    /// returns whether the file arrived
+    fn finish(
+        self,
+        cli: &Cli,
+        src: &Path,
+        metadata: &fs::Metadata,
+        verified: std::io::Result<bool>,
+        job_progress: &JobProgress,
+    ) -> bool {
+        let Writer {
+            destination,
+            target,
+            path,
+            reason,
+            file,
+            progress_bar,
+            ..
+        } = self;
+
+        drop(progress_bar);
+        drop(file);
+
+        let verified = verified.and_then(|verified| {
+            if verified {
+                destination.tracker.add_completed(&target)?;
+            }
+
+            Ok(verified)
+        });
+
+        match verified {
+            Ok(true) => {
+                events::emit(Event::FileComplete {
+                    src,
+                    dest: &path,
+                    size: metadata.len(),
+                });
+
+                let (src_str, dest_str) = display_paths(cli, src, &path);
+
+                job_progress.log(format!(
+                    "copied {} -> {} ({})",
+                    src_str,
+                    dest_str,
+                    HumanBytes(metadata.len())
+                ));
+
+                job_progress.file_done();
+                true
+            }
+            Ok(false) => {
+                // the copy doesn't take a name the retry would skip
+                if matches!(reason, WriteReason::KeepBoth) {
+                    _ = fs::remove_file(&path);
+                }
+
+                job_progress.file_done();
+                retry(cli, src, metadata, destination, &target, job_progress)
+            }
+            Err(e) => {
+                fail(src, &path, &e, job_progress);
+                false
+            }
+        }
+    }
+}
+
+/// copy a file which failed verification to one destination again, like the
+/// retries of single destination jobs
+fn retry(
+    cli: &Cli,
+    src: &Path,
+    metadata: &fs::Metadata,
+    destination: &FanOutDestination,
+    target: &Path,
+    job_progress: &JobProgress,
+) -> bool {
+    if cli.verification.verify_retries == 0 {
+        return false;
+    }
+
+    events::emit(Event::Retry { src, dest: target });
+    job_progress.add_work(1, metadata.len());
+
+    let result = copy_file(
+        &retry_cli(cli),
+        src,
+        target,
+        destination.root,
+        job_progress,
+        destination.tracker,
+        Arc::new(Mutex::new(vec![])),
+    );
+
+    match result {
+        Ok(outcome) => outcome != Outcome::Skipped,
+        Err(e) => {
+            fail(src, target, &e, job_progress);
+            false
+        }
+    }
+}
+
+fn fail(src: &Path, destination: &Path, e: &Error, job_progress: &JobProgress) {
+    if e.kind() == ErrorKind::Interrupted {
+        signals::mark_remaining(src, destination);
+    } else {
+        report_copy_error(src, destination, e, job_progress);
+    }
+}
+
+/// the blake3 hash of a file, advancing the bar by the bytes read
+fn hash_file(
+    cli: &Cli,
+    path: &Path,
+    progress_bar: Option<&FileBar>,
+) -> std::io::Result<blake3::Hash> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; cli.buf_size.to_bytes()];
+
+    loop {
+        signals::wait_while_paused(progress_bar.map(|bar| &**bar));
+
+        let bytes_read = file.read(&mut buffer)?;
+
+        if bytes_read == 0 {
+            return Ok(hasher.finalize());
+        }
+
+        hasher.update(&buffer[..bytes_read]);
+
+        if let Some(progress_bar) = progress_bar {
+            progress_bar.inc(bytes_read as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cli::ProgressMode,
+        progress_bar::{JobProgress, Theme},
+    };
+    use clap::Parser;
+    use std::{fs::OpenOptions, time::Duration};
+
+    /// a writer which wrote the start of its file in a previous run already
+    fn writer<'a>(
+        destination: &'a FanOutDestination<'a>,
+        path: PathBuf,
+        written: &[u8],
+        job_progress: &JobProgress,
+    ) -> Writer<'a> {
+        fs::write(&path, written).unwrap();
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+
+        Writer {
+            destination,
+            target: path.clone(),
+            path,
+            reason: WriteReason::Tracked,
+            file,
+            progress_bar: job_progress.progress_bar(0),
+            position: written.len() as u64,
+        }
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let dir = std::env::temp_dir().join(format!("pcp-read-chunks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("src"), vec![1; 3000]).unwrap();
+
+        let cli = Cli::parse_from(["pcp", "--buf-size", "1KiB", "s", "d"]);
+        let (sender, receiver) = mpsc::sync_channel(10);
+
+        let mut src_file = File::open(dir.join("src")).unwrap();
+        read_chunks(&cli, &mut src_file, 1000, 3000, vec![sender], None).unwrap();
+
+        let chunks = receiver
+            .iter()
+            .map(|(offset, chunk)| (offset, chunk.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(chunks, [(1000, 1024), (2024, 976)]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stream_resumes_each_writer() {
+        let dir = std::env::temp_dir().join(format!("pcp-fan-out-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let content = (0..10_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let src = dir.join("src");
+        fs::write(&src, &content).unwrap();
+        let metadata = src.metadata().unwrap();
+
+        let cli = Cli::parse_from(["pcp", "--verify", "--buf-size", "1KiB", "s", "d"]);
+        let tracker =
+            CompletionTracker::open(&dir, false, cli.checkpoint_interval.clone()).unwrap();
+        let completed = HashSet::new();
+        let destination = FanOutDestination {
+            root: &dir,
+            tracker: &tracker,
+            completed: &completed,
+        };
+        let job_progress = JobProgress::new(
+            1,
+            &ProgressMode::None,
+            Duration::from_secs(10),
+            Theme::from_cli(&cli),
+        )
+        .unwrap();
+
+        let stream_to = |written: [&[u8]; 2]| {
+            let writers = written
+                .iter()
+                .enumerate()
+                .map(|(i, written)| {
+                    writer(
+                        &destination,
+                        dir.join(i.to_string()),
+                        written,
+                        &job_progress,
+                    )
+                })
+                .collect();
+
+            let mut src_file = File::open(&src).unwrap();
+            let (hash, results) =
+                stream(&cli, &src, &mut src_file, &metadata, writers, &job_progress);
+
+            for (writer, result) in results {
+                result.unwrap();
+                assert_eq!(writer.position, content.len() as u64);
+                assert_eq!(fs::read(&writer.path).unwrap(), content);
+            }
+
+            hash
+        };
+
+        // a new copy and one which was interrupted in the middle of a chunk
+        assert_eq!(
+            stream_to([&[], &content[..3000]]),
+            Some(blake3::hash(&content))
+        );
+
+        // the source is only read from the first byte missing in any copy,
+        // so it has to be hashed separately
+        assert_eq!(stream_to([&content[..5000], &content[..3000]]), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,97 +1,124 @@
 use filetime::{set_file_times, FileTime};
-use indicatif::MultiProgress;
+use indicatif::HumanBytes;
 use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::process::exit;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use walkdir::DirEntry;
 
+use crate::backup::{self, backup_dir, is_backup};
+use crate::checksum;
 use crate::cli::Cli;
-use crate::progress::CompletionTracker;
-use crate::progress_bar::{create_progress_bar, create_verify_bar};
+use crate::events::{self, DeleteReason, Event, SkipReason, WriteReason};
+use crate::filter::Filter;
+use crate::path_utils::keep_both_path;
+use crate::progress::{has_progress, state_dir, CompletionTracker};
+use crate::progress_bar::JobProgress;
+use crate::prompt::{self, Answer};
+use crate::signals;
+
+pub const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// what was done with a source file, which decides whether --move may delete it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// the source was written to the destination
+    Written,
+    /// the destination has the content of the source already, as far as
+    /// the overwrite mode can tell
+    Identical,
+    /// the destination doesn't hold the content of the source
+    Skipped,
+}
+
+impl From<SkipReason> for Outcome {
+    fn from(reason: SkipReason) -> Self {
+        match reason {
+            SkipReason::SameSize
+            | SkipReason::Unchanged
+            | SkipReason::SameContent
+            | SkipReason::AlreadyCompleted => Outcome::Identical,
+            SkipReason::Exists
+            | SkipReason::NotNewer
+            | SkipReason::Declined
+            | SkipReason::Conflict => Outcome::Skipped,
+        }
+    }
+}
+
+/// the overwrite mode applied to a destination
+pub enum Prepared {
+    /// write the source to this path
+    Write(PathBuf, WriteReason),
+    /// there is nothing to write
+    Done(Outcome),
+}
 
 pub fn copy_file(
     cli: &Cli,
     src: &Path,
     destination: &Path,
-    multi_progress: &MultiProgress,
+    root: &Path,
+    job_progress: &JobProgress,
     completed_tracker: &CompletionTracker,
     retries: Arc<Mutex<Vec<PathBuf>>>,
-) -> std::io::Result<()> {
+) -> std::io::Result<Outcome> {
     let mut src_file = File::open(src)?;
     let metadata = src_file.metadata()?;
     let total_size = metadata.len();
+    let target = destination;
 
-    match (&cli.overwrite, &cli.use_progress) {
-        (_, true) => {
-            // Proceed with writing the file
-        }
-        (crate::cli::OverwriteMode::Never, _) => {
-            if destination.exists() {
-                return Ok(());
-            }
-        }
-        (crate::cli::OverwriteMode::SizeDiffers, _) => {
-            if destination.exists() {
-                let dest_size = destination.metadata()?.len();
-
-                if dest_size == total_size {
-                    return Ok(());
-                }
-            }
-        }
-        (crate::cli::OverwriteMode::Always, _) => {
-            // Proceed with writing the file
-        }
-    }
+    let (destination, reason) = match prepare_write(
+        cli,
+        src,
+        &metadata,
+        target,
+        root,
+        completed_tracker,
+        job_progress,
+    )? {
+        Prepared::Write(destination, reason) => (destination, reason),
+        Prepared::Done(outcome) => return Ok(outcome),
+    };
 
-    let mut dest_file = OpenOptions::new()
-        .write(true)
-        .read(true)
-        .create(true)
-        .truncate(!cli.use_progress)
-        .open(destination)?;
+    let destination = destination.as_path();
+    let resume = resumable(completed_tracker.root(), destination);
+    let mut dest_file = open_destination(destination, resume)?;
 
     // Create a progress bar for the file
-    let progress_bar = multi_progress.add(create_progress_bar(total_size).unwrap());
+    let progress_bar = job_progress.progress_bar(total_size);
 
-    let (src_str, dest_str) = if cli.absolute_paths {
-        (src.to_str().unwrap(), destination.to_str().unwrap())
-    } else {
-        (
-            src.strip_prefix(std::env::current_dir().expect("Error getting current dir"))
-                .unwrap_or(src)
-                .to_str()
-                .unwrap(),
-            destination
-                .strip_prefix(std::env::current_dir().expect("Error getting current dir"))
-                .unwrap_or(src)
-                .to_str()
-                .unwrap(),
-        )
-    };
+    let (src_str, dest_str) = display_paths(cli, src, destination);
 
-    progress_bar.set_message(format!("{} -> {}", src_str, dest_str));
+    progress_bar.set_message(job_progress.file_message(src_str, dest_str));
 
     let buf_size = cli.buf_size.to_bytes();
 
     copy_chunks(
+        src,
         destination,
+        reason,
         &mut src_file,
         &metadata,
         total_size,
         &mut dest_file,
         &progress_bar,
+        job_progress,
         buf_size,
         completed_tracker,
     )?;
 
-    if !cli.verification.verify
+    drop(progress_bar);
+
+    let verified = !cli.verification.verify
         || verify(
             src,
-            multi_progress,
+            destination,
+            job_progress,
             retries,
             &mut src_file,
             total_size,
@@ -99,37 +126,312 @@ pub fn copy_file(
             src_str,
             dest_str,
             buf_size,
-        )?
+        )?;
+
+    if verified {
+        // recorded under the path the source maps to, which is where a resumed run looks
+        completed_tracker.add_completed(target)?;
+
+        events::emit(Event::FileComplete {
+            src,
+            dest: destination,
+            size: total_size,
+        });
+
+        job_progress.log(format!(
+            "copied {} -> {} ({})",
+            src_str,
+            dest_str,
+            HumanBytes(total_size)
+        ));
+    } else if matches!(reason, WriteReason::KeepBoth) {
+        // the copy doesn't take a name the retry would skip
+        drop(dest_file);
+        fs::remove_file(destination)?;
+    }
+
+    job_progress.file_done();
+
+    Ok(if verified {
+        Outcome::Written
+    } else {
+        Outcome::Skipped
+    })
+}
+
+/// apply the overwrite mode to a destination. skips or only updates the times of
+/// the destination if there is nothing to write, otherwise returns the path to write
+/// to and why, after backing up the file it replaces
+pub fn prepare_write(
+    cli: &Cli,
+    src: &Path,
+    metadata: &fs::Metadata,
+    destination: &Path,
+    root: &Path,
+    tracker: &CompletionTracker,
+    job_progress: &JobProgress,
+) -> std::io::Result<Prepared> {
+    let total_size = metadata.len();
+    let state_root = tracker.root();
+
+    let decision = match decide(cli, src, metadata, destination, state_root)? {
+        Decision::Ask => match prompt::ask(src, destination)? {
+            Answer::Overwrite => Decision::Write(WriteReason::Confirmed),
+            Answer::Skip => Decision::Skip(SkipReason::Declined),
+            Answer::Rename => keep_both(cli, src, metadata, destination, state_root)?,
+        },
+        decision => decision,
+    };
+
+    let (destination, reason) = match decision {
+        Decision::Write(reason) => (destination.to_path_buf(), reason),
+        Decision::Ask => unreachable!("Answered above"),
+        Decision::KeepBoth(renamed) => (renamed, WriteReason::KeepBoth),
+        Decision::Skip(reason) => {
+            skip_file(src, destination, reason, total_size, job_progress);
+            return Ok(Prepared::Done(reason.into()));
+        }
+        Decision::MetadataUpdate => {
+            set_file_times(
+                destination,
+                FileTime::from_system_time(metadata.accessed()?),
+                FileTime::from_system_time(metadata.modified()?),
+            )?;
+
+            job_progress.skip_files(1, total_size);
+
+            events::emit(Event::MetadataUpdate {
+                src,
+                dest: destination,
+            });
+
+            return Ok(Prepared::Done(Outcome::Identical));
+        }
+    };
+
+    // a partially written file is still the one written by pcp, so only replaced files are backed up
+    if !matches!(reason, WriteReason::New | WriteReason::KeepBoth)
+        && !resumable(state_root, &destination)
     {
-        completed_tracker.add_completed(destination)?;
+        backup::back_up(cli, root, &destination)?;
     }
 
-    Ok(())
+    checksum::invalidate(&destination);
+
+    Ok(Prepared::Write(destination, reason))
+}
+
+/// open a destination for writing. a file partially written by a previous run
+/// is kept, so it can be resumed
+pub fn open_destination(destination: &Path, resume: bool) -> std::io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .read(true)
+        .create(true)
+        .truncate(!resume)
+        .open(destination)
+}
+
+/// paths as shown on the progress bars, relative to the current directory
+/// unless --absolute-paths is passed
+pub fn display_paths<'a>(cli: &Cli, src: &'a Path, destination: &'a Path) -> (&'a str, &'a str) {
+    if cli.absolute_paths {
+        return (src.to_str().unwrap(), destination.to_str().unwrap());
+    }
+
+    let current_dir = std::env::current_dir().expect("Error getting current dir");
+
+    (
+        src.strip_prefix(&current_dir)
+            .unwrap_or(src)
+            .to_str()
+            .unwrap(),
+        destination
+            .strip_prefix(&current_dir)
+            .unwrap_or(destination)
+            .to_str()
+            .unwrap(),
+    )
+}
+
+#[derive(Debug, Clone)]
+pub enum Decision {
+    Write(WriteReason),
+    /// write to this new name, the destination has a different content
+    KeepBoth(PathBuf),
+    /// the destination exists, ask what to do with it
+    Ask,
+    Skip(SkipReason),
+    /// the same content according to --overwrite checksum,
+    /// so only bring the timestamps in line with the source
+    MetadataUpdate,
+}
+
+/// decide whether to write a file based on the overwrite mode, without touching anything.
+/// `state_root` is the directory holding the .pcp/ state with --use-progress
+pub fn decide(
+    cli: &Cli,
+    src: &Path,
+    metadata: &fs::Metadata,
+    destination: &Path,
+    state_root: Option<&Path>,
+) -> std::io::Result<Decision> {
+    let decision = match &cli.overwrite {
+        _ if !destination.exists() => Decision::Write(WriteReason::New),
+        // a file partially written by a previous run is finished
+        _ if resumable(state_root, destination) => Decision::Write(WriteReason::Tracked),
+        crate::cli::OverwriteMode::KeepBoth => {
+            keep_both(cli, src, metadata, destination, state_root)?
+        }
+        crate::cli::OverwriteMode::Interactive => Decision::Ask,
+        crate::cli::OverwriteMode::Never => Decision::Skip(SkipReason::Exists),
+        crate::cli::OverwriteMode::SizeDiffers => {
+            let dest_metadata = destination.metadata()?;
+
+            if dest_metadata.len() != metadata.len() {
+                Decision::Write(WriteReason::SizeDiffers)
+            } else {
+                Decision::Skip(SkipReason::SameSize)
+            }
+        }
+        crate::cli::OverwriteMode::Newer => {
+            let dest_metadata = destination.metadata()?;
+
+            match compare_mtimes(metadata, &dest_metadata, cli.mtime_tolerance) {
+                Ordering::Greater => Decision::Write(WriteReason::Newer),
+                _ => Decision::Skip(SkipReason::NotNewer),
+            }
+        }
+        crate::cli::OverwriteMode::SizeOrMtimeDiffers => {
+            let dest_metadata = destination.metadata()?;
+
+            if dest_metadata.len() != metadata.len() {
+                Decision::Write(WriteReason::SizeDiffers)
+            } else if compare_mtimes(metadata, &dest_metadata, cli.mtime_tolerance)
+                != Ordering::Equal
+            {
+                Decision::Write(WriteReason::MtimeDiffers)
+            } else {
+                Decision::Skip(SkipReason::Unchanged)
+            }
+        }
+        crate::cli::OverwriteMode::Checksum => {
+            let dest_metadata = destination.metadata()?;
+
+            if dest_metadata.len() != metadata.len() {
+                Decision::Write(WriteReason::SizeDiffers)
+            } else if checksum::hash(src, metadata)? != checksum::hash(destination, &dest_metadata)?
+            {
+                Decision::Write(WriteReason::ContentDiffers)
+            } else if compare_mtimes(metadata, &dest_metadata, cli.mtime_tolerance)
+                != Ordering::Equal
+            {
+                Decision::MetadataUpdate
+            } else {
+                Decision::Skip(SkipReason::SameContent)
+            }
+        }
+        crate::cli::OverwriteMode::Always => {
+            // Proceed with writing the file
+            Decision::Write(WriteReason::Always)
+        }
+    };
+
+    Ok(decision)
+}
+
+/// the first name from --keep-both-pattern which is free, unless the destination
+/// or one of the names already taken has the same content as the source.
+/// a name partially written by a previous run is resumed
+fn keep_both(
+    cli: &Cli,
+    src: &Path,
+    metadata: &fs::Metadata,
+    destination: &Path,
+    state_root: Option<&Path>,
+) -> std::io::Result<Decision> {
+    let mut candidate = destination.to_path_buf();
+
+    for n in 1.. {
+        let Ok(dest_metadata) = candidate.metadata() else {
+            return Ok(Decision::KeepBoth(candidate));
+        };
+
+        if candidate != destination && resumable(state_root, &candidate) {
+            return Ok(Decision::KeepBoth(candidate));
+        }
+
+        if dest_metadata.len() == metadata.len()
+            && checksum::hash(src, metadata)? == checksum::hash(&candidate, &dest_metadata)?
+        {
+            return Ok(Decision::Skip(SkipReason::SameContent));
+        }
+
+        candidate = keep_both_path(&cli.keep_both_pattern, destination, n);
+    }
+
+    unreachable!("Ran out of names for {}", destination.display())
+}
+
+/// whether the .pcp/ state in `state_root` holds the progress of a partially written file
+pub fn resumable(state_root: Option<&Path>, path: &Path) -> bool {
+    state_root.is_some_and(|state_root| has_progress(state_root, path))
+}
+
+/// compare the modification times of a source and its destination.
+/// times within the tolerance of each other are equal
+fn compare_mtimes(src: &fs::Metadata, dest: &fs::Metadata, tolerance: Duration) -> Ordering {
+    let (Ok(src_time), Ok(dest_time)) = (src.modified(), dest.modified()) else {
+        return Ordering::Equal;
+    };
+
+    match src_time.duration_since(dest_time) {
+        Ok(difference) if difference <= tolerance => Ordering::Equal,
+        Ok(_) => Ordering::Greater,
+        Err(e) if e.duration() <= tolerance => Ordering::Equal,
+        Err(_) => Ordering::Less,
+    }
+}
+
+fn skip_file(
+    src: &Path,
+    destination: &Path,
+    reason: SkipReason,
+    total_size: u64,
+    job_progress: &JobProgress,
+) {
+    job_progress.skip_files(1, total_size);
+
+    events::emit(Event::Skip {
+        src,
+        dest: destination,
+        reason,
+    });
 }
 
 fn copy_chunks(
+    src: &Path,
     destination: &Path,
+    reason: WriteReason,
     src_file: &mut File,
     metadata: &fs::Metadata,
     total_size: u64,
     dest_file: &mut File,
     progress_bar: &indicatif::ProgressBar,
+    job_progress: &JobProgress,
     buf_size: usize,
     completed_tracker: &CompletionTracker,
 ) -> std::io::Result<()> {
     let mut buffer = vec![0; buf_size];
     let mut bytes_copied = 0;
 
-    let file_name = destination
-        .file_name()
-        .expect("Destination should have a file name");
-
-    let progress = completed_tracker.add_progress_file(file_name, total_size)?;
+    let progress = completed_tracker.add_progress_file(destination, total_size)?;
 
     if let Some(progress) = progress {
         src_file.seek(SeekFrom::Start(progress.current))?;
         dest_file.seek(SeekFrom::Start(progress.current))?;
         progress_bar.set_position(progress.current);
+        job_progress.inc_bytes(progress.current);
         bytes_copied = progress.current;
 
         // TODO: handle this case more gracefully
@@ -139,7 +441,35 @@ fn copy_chunks(
         assert_eq!(metadata.len(), progress.total);
     }
 
+    events::emit(Event::FileStart {
+        src,
+        dest: destination,
+        size: total_size,
+        reason,
+        resumed_at: bytes_copied,
+    });
+
+    let mut last_progress_event = Instant::now();
+
     while bytes_copied < total_size {
+        // without progress tracking a partial file can't be resumed,
+        // so in that case the file is finished before stopping
+        if completed_tracker.is_enabled() && signals::interrupted() {
+            completed_tracker.flush_progress(destination, bytes_copied)?;
+
+            progress_bar.abandon_with_message(format!(
+                "interrupted at {} of {} bytes",
+                bytes_copied, total_size
+            ));
+
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "Interrupted by signal",
+            ));
+        }
+
+        signals::wait_while_paused(Some(progress_bar));
+
         let bytes_read = src_file.read(&mut buffer)?;
 
         if bytes_read == 0 {
@@ -150,8 +480,20 @@ fn copy_chunks(
 
         dest_file.write_all(chunk)?;
         bytes_copied += bytes_read as u64;
-        completed_tracker.write_progress(file_name, bytes_copied)?;
+        completed_tracker.write_progress(destination, bytes_copied)?;
         progress_bar.set_position(bytes_copied);
+        job_progress.inc_bytes(bytes_read as u64);
+
+        if events::json_enabled() && last_progress_event.elapsed() >= PROGRESS_EVENT_INTERVAL {
+            last_progress_event = Instant::now();
+
+            events::emit(Event::FileProgress {
+                src,
+                dest: destination,
+                bytes: bytes_copied,
+                size: total_size,
+            });
+        }
     }
 
     progress_bar.finish();
@@ -162,14 +504,15 @@ fn copy_chunks(
         FileTime::from_system_time(metadata.modified()?),
     )?;
 
-    completed_tracker.remove_progress_file(file_name)?;
+    completed_tracker.remove_progress_file(destination)?;
 
     Ok(())
 }
 
 fn verify(
     src: &Path,
-    multi_progress: &MultiProgress,
+    destination: &Path,
+    job_progress: &JobProgress,
     retries: Arc<Mutex<Vec<PathBuf>>>,
     src_file: &mut File,
     total_size: u64,
@@ -178,8 +521,8 @@ fn verify(
     dest_str: &str,
     buf_size: usize,
 ) -> std::io::Result<bool> {
-    let verify_bar = multi_progress.add(create_verify_bar(total_size).unwrap());
-    verify_bar.set_message(format!("{} -> {}", src_str, dest_str));
+    let verify_bar = job_progress.verify_bar(total_size);
+    verify_bar.set_message(job_progress.file_message(src_str, dest_str));
 
     let mut bytes_verified = 0;
     let mut src_hash_buf = vec![0; buf_size];
@@ -191,6 +534,8 @@ fn verify(
     let mut different = false;
 
     while bytes_verified < total_size {
+        signals::wait_while_paused(Some(&verify_bar));
+
         let src_bytes_read = src_file.read(&mut src_hash_buf)?;
 
         if src_bytes_read == 0 {
@@ -225,6 +570,12 @@ fn verify(
         verify_bar.inc(src_bytes_read as u64);
     }
 
+    events::emit(Event::Verify {
+        src,
+        dest: destination,
+        ok: !different,
+    });
+
     if different {
         eprintln!("  Verification failed for {}", dest_str);
 
@@ -244,7 +595,7 @@ pub fn copy_files_par(
     destination: &Path,
     completion_tracker: &CompletionTracker,
     files: &Vec<DirEntry>,
-    multi_progress: &MultiProgress,
+    job_progress: &JobProgress,
 ) -> std::io::Result<()> {
     let retries = Arc::new(Mutex::new(vec![]));
 
@@ -258,7 +609,7 @@ pub fn copy_files_par(
                 relative_path,
                 destination,
                 cli,
-                multi_progress,
+                job_progress,
                 completion_tracker,
                 retries.clone(),
             )?;
@@ -283,24 +634,29 @@ pub fn copy_files_par(
                 eprintln!("{}", failed.display());
             }
 
-            exit(1);
+            return Ok(());
         }
         _ => {}
     }
 
     retries.par_iter().try_for_each(|path| {
         let prefix = source.to_str().expect("Invalid path");
+        job_progress.add_work(1, path.metadata().map(|m| m.len()).unwrap_or(0));
 
         if let Ok(relative_path) = path.strip_prefix(prefix) {
-            let mut cli = cli.clone();
-            cli.overwrite = crate::cli::OverwriteMode::Always;
+            events::emit(Event::Retry {
+                src: path,
+                dest: &destination.join(relative_path),
+            });
+
+            let cli = retry_cli(cli);
 
             create_dirs_and_copy_file(
                 path,
                 relative_path,
                 destination,
                 &cli,
-                multi_progress,
+                job_progress,
                 completion_tracker,
                 Arc::new(Mutex::new(vec![])),
             )?;
@@ -318,7 +674,7 @@ pub fn move_files_par(
     destination: &Path,
     completion_tracker: &CompletionTracker,
     files: &Vec<DirEntry>,
-    multi_progress: &MultiProgress,
+    job_progress: &JobProgress,
 ) -> std::io::Result<()> {
     let retries = Arc::new(Mutex::new(vec![]));
 
@@ -327,22 +683,19 @@ pub fn move_files_par(
         let prefix = source.to_str().expect("Invalid path");
 
         if let Ok(relative_path) = path.strip_prefix(prefix) {
-            create_dirs_and_copy_file(
+            let outcome = create_dirs_and_copy_file(
                 path,
                 relative_path,
                 destination,
                 cli,
-                multi_progress,
+                job_progress,
                 completion_tracker,
                 retries.clone(),
             )?;
 
-            if !retries
-                .lock()
-                .expect("failed to lock retries")
-                .contains(&path.to_path_buf())
-            {
-                delete_file(path);
+            // a file which failed verification is retried below
+            if outcome != Outcome::Skipped {
+                delete_file(path, DeleteReason::Moved);
             }
         } else {
             eprintln!("Error: Unable to get relative path");
@@ -363,35 +716,35 @@ pub fn move_files_par(
                 eprintln!("{}", failed.display());
             }
 
-            exit(1);
+            return Ok(());
         }
         _ => {}
     }
 
     retries.par_iter().try_for_each(|path| {
         let prefix = source.to_str().expect("Invalid path");
+        job_progress.add_work(1, path.metadata().map(|m| m.len()).unwrap_or(0));
 
         if let Ok(relative_path) = path.strip_prefix(prefix) {
-            let mut cli = cli.clone();
-            cli.overwrite = crate::cli::OverwriteMode::Always;
-            let retries = Arc::new(Mutex::new(vec![]));
+            events::emit(Event::Retry {
+                src: path,
+                dest: &destination.join(relative_path),
+            });
 
-            create_dirs_and_copy_file(
+            let cli = retry_cli(cli);
+
+            let outcome = create_dirs_and_copy_file(
                 path,
                 relative_path,
                 destination,
                 &cli,
-                multi_progress,
+                job_progress,
                 completion_tracker,
-                retries.clone(),
+                Arc::new(Mutex::new(vec![])),
             )?;
 
-            if !retries
-                .lock()
-                .expect("failed to lock retries")
-                .contains(&path.to_path_buf())
-            {
-                delete_file(path);
+            if outcome != Outcome::Skipped {
+                delete_file(path, DeleteReason::Moved);
             }
         }
 
@@ -401,43 +754,401 @@ pub fn move_files_par(
     Ok(())
 }
 
+/// the options to copy a file again with after it failed verification
+pub fn retry_cli(cli: &Cli) -> Cli {
+    let mut cli = cli.clone();
+
+    // a failed keep-both copy was removed, so its name is picked again.
+    // interactive jobs ask again
+    if !matches!(
+        cli.overwrite,
+        crate::cli::OverwriteMode::KeepBoth | crate::cli::OverwriteMode::Interactive
+    ) {
+        cli.overwrite = crate::cli::OverwriteMode::Always;
+    }
+
+    // the failed copy isn't worth keeping, and would replace a simple backup
+    cli.backup = None;
+    cli
+}
+
 fn create_dirs_and_copy_file(
     path: &Path,
     relative_path: &Path,
     destination: &Path,
     cli: &Cli,
-    multi_progress: &MultiProgress,
+    job_progress: &JobProgress,
     completion_tracker: &CompletionTracker,
     retries: Arc<Mutex<Vec<PathBuf>>>,
-) -> std::io::Result<()> {
+) -> std::io::Result<Outcome> {
     let destination_path = if relative_path == Path::new("") {
         destination.to_path_buf()
     } else {
         destination.join(relative_path)
     };
 
+    signals::wait_while_paused(None);
+
+    if signals::interrupted() {
+        signals::mark_remaining(path, &destination_path);
+        return Ok(Outcome::Skipped);
+    }
+
     if let Some(parent) = destination_path.parent() {
         fs::create_dir_all(parent).unwrap();
     }
 
-    if let Err(e) = copy_file(
+    match copy_file(
         cli,
         path,
         &destination_path,
-        multi_progress,
+        destination,
+        job_progress,
         completion_tracker,
         retries.clone(),
     ) {
-        eprintln!("Error copying file: {:?}", e);
+        Ok(outcome) => Ok(outcome),
+        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+            signals::mark_remaining(path, &destination_path);
+            Ok(Outcome::Skipped)
+        }
+        Err(e) => {
+            report_copy_error(path, &destination_path, &e, job_progress);
+            Ok(Outcome::Skipped)
+        }
     }
+}
 
-    Ok(())
+pub fn report_copy_error(
+    src: &Path,
+    destination: &Path,
+    e: &std::io::Error,
+    job_progress: &JobProgress,
+) {
+    eprintln!("Error copying file: {:?}", e);
+
+    events::emit(Event::Error {
+        src: Some(src),
+        dest: Some(destination),
+        message: e.to_string(),
+    });
+
+    job_progress.file_done();
 }
 
-pub fn delete_file(path: &Path) {
+/// files in the destination which aren't provided by any source of the job.
+/// the job state in .pcp/, the --backup-dir, backups and files excluded or ignored
+/// by the filter are never considered.
+pub fn purge_candidates(
+    cli: &Cli,
+    source: &Path,
+    destination: &Path,
+    targets: &HashSet<PathBuf>,
+    filter: &Filter,
+) -> Vec<DirEntry> {
+    let state_dir = state_dir(destination);
+    let backup_dir = backup_dir(cli, destination);
+
+    filter
+        .walk(destination)
+        .into_iter()
+        .filter_entry(|e| {
+            e.path() != state_dir
+                && backup_dir.as_deref() != Some(e.path())
+                && filter.allows_entry(destination, e)
+                && !filter.is_other_filesystem(source, destination, e)
+                && !filter.ignores_entry(source, destination, e)
+        })
+        .filter_map(Result::ok)
+        .filter(|e| e.path().is_file())
+        .filter(|e| !targets.contains(e.path()) && !is_backup(cli, e.path()))
+        .collect()
+}
+
+/// move a purged file to its backup path instead of deleting it
+pub fn back_up_file(cli: &Cli, root: &Path, path: &Path) {
+    if let Err(e) = backup::back_up(cli, root, path) {
+        eprintln!("Error backing up file: {:?}", e);
+
+        events::emit(Event::Error {
+            src: None,
+            dest: Some(path),
+            message: e.to_string(),
+        });
+    }
+}
+
+pub fn delete_file(path: &Path, reason: DeleteReason) {
     if path.exists() {
         if let Err(e) = fs::remove_file(path) {
             eprintln!("Error deleting file: {:?}", e);
+
+            events::emit(Event::Error {
+                src: None,
+                dest: Some(path),
+                message: e.to_string(),
+            });
+
+            return;
         }
+
+        events::emit(Event::Delete { path, reason });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_decide_keep_both() {
+        let dir = std::env::temp_dir().join(format!("pcp-keep-both-{}", std::process::id()));
+        fs::create_dir_all(state_dir(&dir)).unwrap();
+
+        let src = dir.join("src.jpg");
+        let destination = dir.join("IMG.jpg");
+        fs::write(&src, "camera-A").unwrap();
+        fs::write(&destination, "camera-B-different").unwrap();
+
+        let cli = Cli::parse_from([
+            "pcp",
+            "--overwrite",
+            "keep-both",
+            "--use-progress",
+            "s",
+            "d",
+        ]);
+        let metadata = src.metadata().unwrap();
+        let decide = || decide(&cli, &src, &metadata, &destination, Some(&dir)).unwrap();
+
+        // a file which wasn't written by pcp is never resumed in place
+        assert!(matches!(decide(), Decision::KeepBoth(path) if path == dir.join("IMG (1).jpg")));
+
+        fs::write(dir.join("IMG (1).jpg"), "camera-C").unwrap();
+        assert!(matches!(decide(), Decision::KeepBoth(path) if path == dir.join("IMG (2).jpg")));
+
+        fs::write(dir.join("IMG (2).jpg"), "camera-A").unwrap();
+        assert!(matches!(decide(), Decision::Skip(SkipReason::SameContent)));
+
+        // a renamed copy which was interrupted is resumed
+        fs::write(state_dir(&dir).join("IMG (1).jpg.pcp"), "").unwrap();
+        assert!(matches!(decide(), Decision::KeepBoth(path) if path == dir.join("IMG (1).jpg")));
+
+        fs::write(state_dir(&dir).join("IMG.jpg.pcp"), "").unwrap();
+        assert!(matches!(decide(), Decision::Write(WriteReason::Tracked)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_decide_interactive() {
+        let dir = std::env::temp_dir().join(format!("pcp-interactive-{}", std::process::id()));
+        fs::create_dir_all(state_dir(&dir)).unwrap();
+
+        let src = dir.join("src.txt");
+        let destination = dir.join("a.txt");
+        fs::write(&src, "a").unwrap();
+        fs::write(&destination, "b").unwrap();
+
+        let cli = Cli::parse_from([
+            "pcp",
+            "--overwrite",
+            "interactive",
+            "--use-progress",
+            "s",
+            "d",
+        ]);
+        let metadata = src.metadata().unwrap();
+        let decide = || decide(&cli, &src, &metadata, &destination, Some(&dir)).unwrap();
+
+        assert!(matches!(decide(), Decision::Ask));
+
+        fs::write(state_dir(&dir).join("a.txt.pcp"), "").unwrap();
+        assert!(matches!(decide(), Decision::Write(WriteReason::Tracked)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_decide_use_progress() {
+        let dir = std::env::temp_dir().join(format!("pcp-use-progress-{}", std::process::id()));
+        fs::create_dir_all(state_dir(&dir)).unwrap();
+
+        let src = dir.join("src.txt");
+        let destination = dir.join("a.txt");
+        fs::write(&src, "a").unwrap();
+        fs::write(&destination, "b").unwrap();
+
+        let metadata = src.metadata().unwrap();
+        let decide = |mode: &str| {
+            let cli = Cli::parse_from(["pcp", "--overwrite", mode, "--use-progress", "s", "d"]);
+            decide(&cli, &src, &metadata, &destination, Some(&dir)).unwrap()
+        };
+
+        // files which weren't partially written by pcp follow the overwrite mode
+        assert!(matches!(
+            decide("never"),
+            Decision::Skip(SkipReason::Exists)
+        ));
+        assert!(matches!(
+            decide("size-differs"),
+            Decision::Skip(SkipReason::SameSize)
+        ));
+        assert!(matches!(
+            decide("always"),
+            Decision::Write(WriteReason::Always)
+        ));
+
+        fs::write(state_dir(&dir).join("a.txt.pcp"), "").unwrap();
+        assert!(matches!(
+            decide("never"),
+            Decision::Write(WriteReason::Tracked)
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_decide_checksum_use_progress() {
+        let dir = std::env::temp_dir().join(format!("pcp-checksum-{}", std::process::id()));
+        fs::create_dir_all(state_dir(&dir)).unwrap();
+
+        let src = dir.join("src.txt");
+        let destination = dir.join("a.txt");
+        fs::write(&src, "same").unwrap();
+        fs::write(&destination, "same").unwrap();
+        filetime::set_file_mtime(
+            &destination,
+            FileTime::from_last_modification_time(&src.metadata().unwrap()),
+        )
+        .unwrap();
+
+        let cli = Cli::parse_from(["pcp", "--overwrite", "checksum", "--use-progress", "s", "d"]);
+        let metadata = src.metadata().unwrap();
+        let decide = || decide(&cli, &src, &metadata, &destination, Some(&dir)).unwrap();
+
+        assert!(matches!(decide(), Decision::Skip(SkipReason::SameContent)));
+
+        fs::write(&destination, "diff").unwrap();
+        assert!(matches!(
+            decide(),
+            Decision::Write(WriteReason::ContentDiffers)
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_keeps_declined_files() {
+        use crate::{cli::ProgressMode, progress_bar::Theme};
+        use indicatif::{MultiProgress, ProgressDrawTarget};
+
+        let dir = std::env::temp_dir().join(format!("pcp-move-declined-{}", std::process::id()));
+        let (source, destination) = (dir.join("src"), dir.join("dst"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&destination).unwrap();
+        fs::write(source.join("a.txt"), "new").unwrap();
+        fs::write(destination.join("a.txt"), "old").unwrap();
+        fs::write(source.join("b.txt"), "new").unwrap();
+
+        prompt::init_with(
+            Box::new(&b"s\n"[..]),
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+        );
+
+        let cli = Cli::parse_from(["pcp", "--overwrite", "interactive", "--move", "s", "d"]);
+        let tracker =
+            CompletionTracker::open(&destination, false, cli.checkpoint_interval.clone()).unwrap();
+        let job_progress = JobProgress::new(
+            1,
+            &ProgressMode::None,
+            Duration::from_secs(10),
+            Theme::from_cli(&cli),
+        )
+        .unwrap();
+        let files = walkdir::WalkDir::new(&source)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.path().is_file())
+            .collect::<Vec<_>>();
+
+        move_files_par(&cli, &source, &destination, &tracker, &files, &job_progress).unwrap();
+
+        // the declined file stays in the source, the new one is moved
+        assert_eq!(fs::read_to_string(source.join("a.txt")).unwrap(), "new");
+        assert_eq!(
+            fs::read_to_string(destination.join("a.txt")).unwrap(),
+            "old"
+        );
+        assert!(!source.join("b.txt").exists());
+        assert_eq!(
+            fs::read_to_string(destination.join("b.txt")).unwrap(),
+            "new"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resume_nested_file() {
+        use crate::{
+            cli::ProgressMode,
+            progress::{is_completed, read_completed},
+            progress_bar::Theme,
+        };
+
+        let dir = std::env::temp_dir().join(format!("pcp-resume-nested-{}", std::process::id()));
+        let (source, destination) = (dir.join("src"), dir.join("dst"));
+        fs::create_dir_all(source.join("sub")).unwrap();
+        fs::create_dir_all(destination.join("sub")).unwrap();
+        fs::write(source.join("sub/b.txt"), "hello world").unwrap();
+
+        // a previous run was interrupted after writing the start of sub/b.txt
+        let cli = Cli::parse_from(["pcp", "--use-progress", "--backup", "s", "d"]);
+        let target = destination.join("sub/b.txt");
+        let tracker =
+            CompletionTracker::open(&destination, true, cli.checkpoint_interval.clone()).unwrap();
+        tracker.add_progress_file(&target, 11).unwrap();
+        tracker.flush_progress(&target, 5).unwrap();
+        fs::write(&target, "hello").unwrap();
+        drop(tracker);
+
+        // a file with the same name elsewhere isn't taken for it
+        assert!(resumable(Some(&destination), &target));
+        assert!(!resumable(Some(&destination), &destination.join("b.txt")));
+
+        let tracker =
+            CompletionTracker::open(&destination, true, cli.checkpoint_interval.clone()).unwrap();
+        let job_progress = JobProgress::new(
+            1,
+            &ProgressMode::None,
+            Duration::from_secs(10),
+            Theme::from_cli(&cli),
+        )
+        .unwrap();
+
+        let outcome = copy_file(
+            &cli,
+            &source.join("sub/b.txt"),
+            &target,
+            &destination,
+            &job_progress,
+            &tracker,
+            Arc::new(Mutex::new(vec![])),
+        )
+        .unwrap();
+
+        // the partial file is finished rather than backed up and rewritten
+        assert!(matches!(outcome, Outcome::Written));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "hello world");
+        assert!(!destination.join("sub/b.txt~").exists());
+        assert!(!resumable(Some(&destination), &target));
+
+        // the next run finds it completed under the same relative path
+        let completed = read_completed(&destination);
+        assert!(is_completed(&completed, &destination, &target));
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,546 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::Path,
+    time::SystemTime,
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+    cli::{Cli, FileType},
+    ignore_files::IgnoreFiles,
+};
+
+/// include and exclude globs, matched against paths relative to the source
+/// or destination. patterns without a `/` match a name at any depth,
+/// a trailing `/` only matches directories.
+#[derive(Debug)]
+pub struct Filter {
+    include: Patterns,
+    exclude: Patterns,
+    /// set with --use-ignore-files
+    ignore_files: Option<IgnoreFiles>,
+    attributes: Attributes,
+    walk: WalkLimits,
+}
+
+/// limits of the walk, applied to the source as well as to destinations when purging
+#[derive(Debug, Default)]
+struct WalkLimits {
+    one_file_system: bool,
+    max_depth: Option<usize>,
+    min_depth: Option<usize>,
+}
+
+/// size, age and type of the files to copy. unlike the globs these only select
+/// which source files are copied, they don't protect destination files from --purge
+#[derive(Debug, Default)]
+struct Attributes {
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    newer_than: Option<SystemTime>,
+    older_than: Option<SystemTime>,
+    file_types: Vec<FileType>,
+}
+
+#[derive(Debug, Clone)]
+struct Patterns {
+    any: GlobSet,
+    dirs: GlobSet,
+    len: usize,
+}
+
+impl Filter {
+    pub fn from_cli(cli: &Cli) -> std::io::Result<Self> {
+        let mut exclude = cli.exclude.clone();
+
+        for path in &cli.exclude_from {
+            exclude.extend(read_patterns(path)?);
+        }
+
+        Ok(Self {
+            include: Patterns::new(&cli.include)?,
+            exclude: Patterns::new(&exclude)?,
+            ignore_files: cli.use_ignore_files.then(IgnoreFiles::default),
+            attributes: Attributes {
+                min_size: cli.min_size.as_ref().map(|s| s.to_bytes() as u64),
+                max_size: cli.max_size.as_ref().map(|s| s.to_bytes() as u64),
+                newer_than: cli.newer_than,
+                older_than: cli.older_than,
+                file_types: cli.file_types.clone(),
+            },
+            walk: WalkLimits {
+                one_file_system: cli.one_file_system,
+                max_depth: cli.max_depth,
+                min_depth: cli.min_depth,
+            },
+        })
+    }
+
+    /// whether every file passes the filter
+    pub fn is_empty(&self) -> bool {
+        self.include.len == 0
+            && self.exclude.len == 0
+            && self.ignore_files.is_none()
+            && self.attributes.is_empty()
+            && self.walk.is_empty()
+    }
+
+    /// a walk of `root` with the depth limits applied. they only count below a
+    /// directory, a single file is always walked
+    pub fn walk(&self, root: &Path) -> WalkDir {
+        let mut walk_dir = WalkDir::new(root);
+
+        if !root.is_dir() {
+            return walk_dir;
+        }
+
+        if let Some(max_depth) = self.walk.max_depth {
+            walk_dir = walk_dir.max_depth(max_depth);
+        }
+
+        if let Some(min_depth) = self.walk.min_depth {
+            walk_dir = walk_dir.min_depth(min_depth);
+        }
+
+        walk_dir
+    }
+
+    /// whether a directory of a walk starting at `root` is a mount point which
+    /// --one-file-system doesn't descend into. the device is always compared
+    /// against the source, so destinations mirror what was skipped there
+    pub fn is_other_filesystem(&self, source: &Path, root: &Path, entry: &DirEntry) -> bool {
+        if !self.walk.one_file_system || !entry.file_type().is_dir() {
+            return false;
+        }
+
+        let Ok(relative_path) = entry.path().strip_prefix(root) else {
+            return false;
+        };
+
+        match (device(source), device(&source.join(relative_path))) {
+            (Some(source_device), Some(device)) => source_device != device,
+            _ => false,
+        }
+    }
+
+    /// whether a file found by the walk matches the size, age and type filters
+    pub fn selects(&self, entry: &DirEntry) -> bool {
+        self.attributes.is_empty() || self.attributes.matches(entry)
+    }
+
+    /// whether an entry of a walk starting at `root` passes the filter
+    pub fn allows_entry(&self, root: &Path, entry: &DirEntry) -> bool {
+        let Ok(relative_path) = entry.path().strip_prefix(root) else {
+            return true;
+        };
+
+        if entry.file_type().is_dir() {
+            self.allows_dir(relative_path)
+        } else {
+            self.allows_file(relative_path)
+        }
+    }
+
+    /// whether an entry of a walk starting at `root` is ignored by the ignore files of `source`.
+    /// `root` is either the source itself or a destination of it
+    pub fn ignores_entry(&self, source: &Path, root: &Path, entry: &DirEntry) -> bool {
+        let (Some(ignore_files), Ok(relative_path)) =
+            (&self.ignore_files, entry.path().strip_prefix(root))
+        else {
+            return false;
+        };
+
+        ignore_files.is_ignored(source, relative_path, entry.file_type().is_dir())
+    }
+
+    /// whether the walk should descend into this directory
+    pub fn allows_dir(&self, relative_path: &Path) -> bool {
+        relative_path == Path::new("") || !self.exclude.matches(relative_path, true)
+    }
+
+    /// whether this file should be copied, or kept when purging.
+    /// excludes win over includes
+    pub fn allows_file(&self, relative_path: &Path) -> bool {
+        if relative_path == Path::new("") {
+            return true;
+        }
+
+        if self.exclude.matches(relative_path, false) {
+            return false;
+        }
+
+        self.include.len == 0
+            || self.include.matches(relative_path, false)
+            || relative_path
+                .ancestors()
+                .skip(1)
+                .filter(|ancestor| *ancestor != Path::new(""))
+                .any(|ancestor| self.include.matches(ancestor, true))
+    }
+}
+
+impl WalkLimits {
+    fn is_empty(&self) -> bool {
+        !self.one_file_system && self.max_depth.is_none() && self.min_depth.is_none()
+    }
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    path.symlink_metadata().ok().map(|metadata| metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_path: &Path) -> Option<u64> {
+    None
+}
+
+impl Attributes {
+    fn is_empty(&self) -> bool {
+        self.min_size.is_none()
+            && self.max_size.is_none()
+            && self.newer_than.is_none()
+            && self.older_than.is_none()
+            && self.file_types.is_empty()
+    }
+
+    fn matches(&self, entry: &DirEntry) -> bool {
+        // follows symlinks, like the copy itself
+        let Ok(metadata) = entry.path().metadata() else {
+            return false;
+        };
+
+        let size = metadata.len();
+
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+
+        if self.newer_than.is_some() || self.older_than.is_some() {
+            let Ok(modified) = metadata.modified() else {
+                return false;
+            };
+
+            if self.newer_than.is_some_and(|t| modified <= t)
+                || self.older_than.is_some_and(|t| modified >= t)
+            {
+                return false;
+            }
+        }
+
+        self.file_types.is_empty()
+            || self.file_types.iter().any(|file_type| match file_type {
+                FileType::File => !entry.path_is_symlink(),
+                FileType::Symlink => entry.path_is_symlink(),
+                FileType::Executable => is_executable(&metadata),
+                FileType::Empty => size == 0,
+            })
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> std::io::Result<Self> {
+        let mut any = GlobSetBuilder::new();
+        let mut dirs = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let (pattern, dir_only) = match pattern.strip_suffix('/') {
+                Some(pattern) => (pattern, true),
+                None => (pattern.as_str(), false),
+            };
+
+            let pattern = match pattern.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if pattern.contains('/') => pattern.to_string(),
+                None => format!("**/{}", pattern),
+            };
+
+            let glob = GlobBuilder::new(&pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+            if dir_only {
+                dirs.add(glob);
+            } else {
+                any.add(glob);
+            }
+        }
+
+        let build = |builder: GlobSetBuilder| {
+            builder
+                .build()
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+        };
+
+        Ok(Self {
+            any: build(any)?,
+            dirs: build(dirs)?,
+            len: patterns.len(),
+        })
+    }
+
+    fn matches(&self, relative_path: &Path, is_dir: bool) -> bool {
+        self.any.is_match(relative_path) || (is_dir && self.dirs.is_match(relative_path))
+    }
+}
+
+/// one pattern per line, empty lines and lines starting with `#` are ignored
+fn read_patterns(path: &Path) -> std::io::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("Failed to read {}: {}", path.display(), e),
+        )
+    })?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(include: &[&str], exclude: &[&str]) -> Filter {
+        let strings =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        Filter {
+            include: Patterns::new(&strings(include)).unwrap(),
+            exclude: Patterns::new(&strings(exclude)).unwrap(),
+            ignore_files: None,
+            attributes: Attributes::default(),
+            walk: WalkLimits::default(),
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = build(&["*.rs", "docs/"], &["target/", "*.tmp", "/build"]);
+
+        assert!(!filter.allows_dir(Path::new("target")));
+        assert!(!filter.allows_dir(Path::new("crates/a/target")));
+        assert!(!filter.allows_dir(Path::new("build")));
+        assert!(filter.allows_dir(Path::new("src/build")));
+
+        assert!(filter.allows_file(Path::new("src/main.rs")));
+        assert!(filter.allows_file(Path::new("docs/guide/intro.md")));
+        assert!(!filter.allows_file(Path::new("docs/notes.tmp")));
+        assert!(!filter.allows_file(Path::new("README.md")));
+
+        // directory patterns don't match files of the same name
+        let filter = build(&[], &["target/"]);
+        assert!(filter.allows_file(Path::new("target")));
+        assert!(filter.allows_file(Path::new("README.md")));
+    }
+
+    fn walk_names(filter: &Filter, root: &Path) -> Vec<String> {
+        let mut names = filter
+            .walk(root)
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|e| e.path().strip_prefix(root).unwrap().display().to_string())
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_walk_depth() {
+        let dir = std::env::temp_dir().join(format!("pcp-walk-depth-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("top.txt"), "").unwrap();
+        std::fs::write(dir.join("a/mid.txt"), "").unwrap();
+        std::fs::write(dir.join("a/b/deep.txt"), "").unwrap();
+
+        let limits = |min_depth, max_depth| Filter {
+            walk: WalkLimits {
+                one_file_system: false,
+                min_depth,
+                max_depth,
+            },
+            ..build(&[], &[])
+        };
+
+        assert_eq!(
+            walk_names(&limits(None, Some(1)), &dir),
+            ["", "a", "top.txt"]
+        );
+        assert_eq!(
+            walk_names(&limits(Some(2), None), &dir),
+            ["a/b", "a/b/deep.txt", "a/mid.txt"]
+        );
+        assert_eq!(
+            walk_names(&limits(Some(1), Some(1)), &dir),
+            ["a", "top.txt"]
+        );
+
+        // a single file is copied no matter the depth limits
+        let file = dir.join("top.txt");
+        assert_eq!(walk_names(&limits(Some(1), None), &file), [""]);
+        assert_eq!(walk_names(&limits(Some(2), Some(3)), &file), [""]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_one_file_system() {
+        let dir = std::env::temp_dir().join(format!("pcp-one-fs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("file.txt"), "").unwrap();
+
+        let mut filter = build(&[], &[]);
+        filter.walk.one_file_system = true;
+        assert!(!filter.is_empty());
+
+        // nothing on the same file system is skipped, and files never are
+        for entry in filter.walk(&dir).into_iter().filter_map(Result::ok) {
+            assert!(!filter.is_other_filesystem(&dir, &dir, &entry));
+        }
+
+        // /proc is a file system of its own on linux
+        #[cfg(target_os = "linux")]
+        {
+            let root = Path::new("/");
+            let proc = WalkDir::new(root)
+                .max_depth(1)
+                .into_iter()
+                .filter_map(Result::ok)
+                .find(|e| e.path() == Path::new("/proc"))
+                .unwrap();
+
+            assert!(filter.is_other_filesystem(root, root, &proc));
+
+            filter.walk.one_file_system = false;
+            assert!(!filter.is_other_filesystem(root, root, &proc));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// the files of a test directory which are selected by the attributes, by name
+    fn selected(attributes: Attributes, dir: &Path) -> Vec<String> {
+        let filter = Filter {
+            attributes,
+            ..build(&[], &[])
+        };
+
+        let mut names = WalkDir::new(dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| filter.selects(e))
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_size_and_time() {
+        use filetime::{set_file_mtime, FileTime};
+        use std::time::Duration;
+
+        let dir = std::env::temp_dir().join(format!("pcp-attributes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        for (name, size, age) in [("small", 9, 3), ("exact", 10, 2), ("large", 11, 1)] {
+            let path = dir.join(name);
+            std::fs::write(&path, vec![0; size]).unwrap();
+            set_file_mtime(&path, FileTime::from_system_time(now - day * age)).unwrap();
+        }
+
+        // sizes are inclusive
+        let sizes = |min_size, max_size| Attributes {
+            min_size,
+            max_size,
+            ..Attributes::default()
+        };
+        assert_eq!(selected(sizes(Some(10), None), &dir), ["exact", "large"]);
+        assert_eq!(selected(sizes(None, Some(10)), &dir), ["exact", "small"]);
+        assert_eq!(selected(sizes(Some(10), Some(10)), &dir), ["exact"]);
+
+        // times are exclusive, a file modified exactly at the time isn't newer or older
+        let times = |newer_than, older_than| Attributes {
+            newer_than,
+            older_than,
+            ..Attributes::default()
+        };
+        let exact = Some(now - day * 2);
+        assert_eq!(selected(times(exact, None), &dir), ["large"]);
+        assert_eq!(selected(times(None, exact), &dir), ["small"]);
+        assert_eq!(
+            selected(times(Some(now - day * 3), Some(now - day)), &dir),
+            ["exact"]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_types() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("pcp-file-types-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        std::fs::write(dir.join("empty"), "").unwrap();
+        std::fs::write(dir.join("script"), "#!/bin/sh").unwrap();
+        std::fs::set_permissions(dir.join("script"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        symlink(dir.join("script"), dir.join("link")).unwrap();
+
+        let types = |file_types: &[FileType]| Attributes {
+            file_types: file_types.to_vec(),
+            ..Attributes::default()
+        };
+
+        assert_eq!(
+            selected(types(&[FileType::File]), &dir),
+            ["empty", "script"]
+        );
+        assert_eq!(selected(types(&[FileType::Symlink]), &dir), ["link"]);
+        // the executable bit is read from the target of a symlink
+        assert_eq!(
+            selected(types(&[FileType::Executable]), &dir),
+            ["link", "script"]
+        );
+        assert_eq!(selected(types(&[FileType::Empty]), &dir), ["empty"]);
+        // any of the types matches
+        assert_eq!(
+            selected(types(&[FileType::Empty, FileType::Symlink]), &dir),
+            ["empty", "link"]
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+
+/// read in this order, so rules of later files take precedence
+pub const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".pcpignore"];
+
+/// ignore files found at any level of a source tree, loaded lazily per directory
+#[derive(Debug, Default)]
+pub struct IgnoreFiles {
+    matchers: Mutex<HashMap<PathBuf, Option<Arc<Gitignore>>>>,
+    /// the directories above each source whose ignore files apply to it
+    parents: Mutex<HashMap<PathBuf, Arc<[PathBuf]>>>,
+}
+
+impl IgnoreFiles {
+    /// whether a path relative to the source is ignored.
+    /// the rules of the closest directory with a matching rule win
+    pub fn is_ignored(&self, source: &Path, relative_path: &Path, is_dir: bool) -> bool {
+        if relative_path == Path::new("") {
+            return false;
+        }
+
+        let path = source.join(relative_path);
+        let parents = self.parents(source);
+
+        let dirs = relative_path
+            .ancestors()
+            .skip(1)
+            .map(|dir| source.join(dir))
+            .chain(parents.iter().cloned());
+
+        for dir in dirs {
+            let Some(matcher) = self.matcher(&dir) else {
+                continue;
+            };
+
+            match matcher.matched(&path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+
+        false
+    }
+
+    /// like git, the ignore files above a source up to the root of the repository
+    /// it's in apply to it as well. outside of a repository only the source counts
+    fn parents(&self, source: &Path) -> Arc<[PathBuf]> {
+        self.parents
+            .lock()
+            .expect("Failed to lock ignore files")
+            .entry(source.to_path_buf())
+            .or_insert_with(|| {
+                let Some(repository) = source.ancestors().find(|dir| dir.join(".git").exists())
+                else {
+                    return Arc::from([]);
+                };
+
+                source
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|dir| dir.starts_with(repository))
+                    .map(Path::to_path_buf)
+                    .collect()
+            })
+            .clone()
+    }
+
+    fn matcher(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        self.matchers
+            .lock()
+            .expect("Failed to lock ignore files")
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| load(dir))
+            .clone()
+    }
+}
+
+fn load(dir: &Path) -> Option<Arc<Gitignore>> {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+
+    for name in IGNORE_FILE_NAMES {
+        let path = dir.join(name);
+
+        if !path.is_file() {
+            continue;
+        }
+
+        found = true;
+
+        if let Some(e) = builder.add(&path) {
+            eprintln!("Error reading {}: {}", path.display(), e);
+        }
+    }
+
+    if !found {
+        return None;
+    }
+
+    match builder.build() {
+        Ok(matcher) => Some(Arc::new(matcher)),
+        Err(e) => {
+            eprintln!("Error reading ignore files in {}: {}", dir.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_ignore_files() {
+        let dir = std::env::temp_dir().join(format!("pcp-ignore-nested-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.log\nbuild/\n").unwrap();
+        std::fs::write(dir.join(".pcpignore"), "!keep.log\n").unwrap();
+        std::fs::write(dir.join("sub/.ignore"), "!*.log\n/local.txt\n").unwrap();
+
+        let ignore_files = IgnoreFiles::default();
+        let ignored = |path: &str, is_dir| ignore_files.is_ignored(&dir, Path::new(path), is_dir);
+
+        assert!(ignored("debug.log", false));
+        assert!(ignored("build", true));
+        assert!(!ignored("build", false));
+        assert!(!ignored("main.rs", false));
+        assert!(!ignored("", true));
+
+        // .pcpignore wins over .gitignore in the same directory
+        assert!(!ignored("keep.log", false));
+
+        // deeper rules win, anchored rules only apply to their own directory
+        assert!(!ignored("sub/debug.log", false));
+        assert!(!ignored("sub/deeper/debug.log", false));
+        assert!(ignored("sub/local.txt", false));
+        assert!(!ignored("sub/deeper/local.txt", false));
+        assert!(!ignored("local.txt", false));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parent_ignore_files() {
+        let dir = std::env::temp_dir().join(format!("pcp-ignore-parent-{}", std::process::id()));
+        let source = dir.join("repo/src");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(dir.join(".gitignore"), "*.txt\n").unwrap();
+        std::fs::write(dir.join("repo/.gitignore"), "*.tmp\n/src/generated\n").unwrap();
+
+        // outside of a repository the directories above the source aren't consulted
+        let ignore_files = IgnoreFiles::default();
+        assert!(!ignore_files.is_ignored(&source, Path::new("a.tmp"), false));
+
+        std::fs::create_dir(dir.join("repo/.git")).unwrap();
+
+        let ignore_files = IgnoreFiles::default();
+        let ignored =
+            |path: &str, is_dir| ignore_files.is_ignored(&source, Path::new(path), is_dir);
+
+        assert!(ignored("a.tmp", false));
+        assert!(ignored("nested/a.tmp", false));
+        assert!(ignored("generated", true));
+        assert!(!ignored("nested/generated", true));
+        // above the repository nothing applies
+        assert!(!ignored("a.txt", false));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
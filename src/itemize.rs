@@ -0,0 +1,209 @@
+use std::{
+    fs::File,
+    io::{BufWriter, IsTerminal, Write},
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use indicatif::MultiProgress;
+
+use crate::events::{DeleteReason, Event, PlanAction, SkipReason, WriteReason};
+
+static SINK: OnceLock<Sink> = OnceLock::new();
+
+struct Sink {
+    writer: Mutex<Box<dyn Write + Send>>,
+    /// set when writing to a terminal which also shows the progress bars
+    multi_progress: Option<MultiProgress>,
+}
+
+/// write a line per file decision to the given file, or stdout if no file is given
+pub fn init(
+    itemize: bool,
+    itemize_file: Option<&Path>,
+    multi_progress: MultiProgress,
+) -> std::io::Result<()> {
+    if !itemize && itemize_file.is_none() {
+        return Ok(());
+    }
+
+    let sink = match itemize_file {
+        Some(path) => Sink {
+            writer: Mutex::new(Box::new(BufWriter::new(File::create(path)?))),
+            multi_progress: None,
+        },
+        None => Sink {
+            writer: Mutex::new(Box::new(std::io::stdout())),
+            multi_progress: std::io::stdout().is_terminal().then_some(multi_progress),
+        },
+    };
+
+    _ = SINK.set(sink);
+    Ok(())
+}
+
+pub fn record(event: &Event) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+
+    let Some(line) = describe(event) else {
+        return;
+    };
+
+    let write = || {
+        let mut writer = sink.writer.lock().expect("Failed to lock itemize sink");
+        _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+    };
+
+    match &sink.multi_progress {
+        Some(multi_progress) => multi_progress.suspend(write),
+        None => write(),
+    }
+}
+
+fn describe_plan(src: Option<&Path>, dest: Option<&Path>, action: &PlanAction) -> String {
+    let paths = match (src, dest) {
+        (Some(src), Some(dest)) => format!("{} -> {}", src.display(), dest.display()),
+        (Some(path), None) | (None, Some(path)) => path.display().to_string(),
+        (None, None) => String::new(),
+    };
+
+    match action {
+        PlanAction::Copy => format!("copy        {}", paths),
+        PlanAction::Overwrite { reason } => {
+            format!("overwrite   {} ({})", paths, describe_write_reason(reason))
+        }
+        PlanAction::Skip { reason } => {
+            format!("skip        {} ({})", paths, describe_skip_reason(reason))
+        }
+        PlanAction::MetadataUpdate => format!("metadata    {} (same content, times differ)", paths),
+        PlanAction::Delete { reason } => {
+            format!("delete      {} ({})", paths, describe_delete_reason(reason))
+        }
+        PlanAction::Backup => format!("backup      {}", paths),
+        PlanAction::KeepBoth => format!("keep both   {}", paths),
+        PlanAction::Ask => format!("ask         {}", paths),
+        PlanAction::Rename => format!("rename      {}", paths),
+    }
+}
+
+fn describe_write_reason(reason: &WriteReason) -> &'static str {
+    match reason {
+        WriteReason::SizeDiffers => "size differs",
+        WriteReason::Always => "overwrite always",
+        WriteReason::Newer => "source is newer",
+        WriteReason::MtimeDiffers => "modification time differs",
+        WriteReason::ContentDiffers => "content differs",
+        WriteReason::KeepBoth => "destination differs, kept both",
+        WriteReason::Confirmed => "confirmed",
+        WriteReason::Tracked => "resumed by --use-progress",
+        WriteReason::New => "new",
+    }
+}
+
+fn describe_skip_reason(reason: &SkipReason) -> &'static str {
+    match reason {
+        SkipReason::Exists => "exists",
+        SkipReason::SameSize => "same size",
+        SkipReason::NotNewer => "source is not newer",
+        SkipReason::Unchanged => "same size and modification time",
+        SkipReason::SameContent => "same content",
+        SkipReason::Declined => "declined",
+        SkipReason::AlreadyCompleted => "completed by a previous run",
+        SkipReason::Conflict => "provided by another source",
+    }
+}
+
+fn describe_delete_reason(reason: &DeleteReason) -> &'static str {
+    match reason {
+        DeleteReason::Purged => "not in source",
+        DeleteReason::Moved => "moved",
+    }
+}
+
+fn describe(event: &Event) -> Option<String> {
+    let line = match event {
+        Event::FileStart {
+            src,
+            dest,
+            resumed_at,
+            ..
+        } if *resumed_at > 0 => format!(
+            "resumed     {} -> {} (at {} bytes)",
+            src.display(),
+            dest.display(),
+            resumed_at
+        ),
+        Event::FileStart {
+            src,
+            dest,
+            reason: WriteReason::New,
+            ..
+        } => format!("created     {} -> {}", src.display(), dest.display()),
+        Event::FileStart {
+            src,
+            dest,
+            reason: WriteReason::KeepBoth,
+            ..
+        } => format!("kept both   {} -> {}", src.display(), dest.display()),
+        Event::FileStart {
+            src, dest, reason, ..
+        } => format!(
+            "overwritten {} -> {} ({})",
+            src.display(),
+            dest.display(),
+            describe_write_reason(reason)
+        ),
+        Event::Skip { src, dest, reason } => format!(
+            "skipped     {} -> {} ({})",
+            src.display(),
+            dest.display(),
+            describe_skip_reason(reason)
+        ),
+        Event::MetadataUpdate { src, dest } => format!(
+            "metadata    {} -> {} (same content, times updated)",
+            src.display(),
+            dest.display()
+        ),
+        Event::Verify {
+            src,
+            dest,
+            ok: false,
+        } => format!(
+            "unverified  {} -> {} (content differs)",
+            src.display(),
+            dest.display()
+        ),
+        Event::Retry { src, dest } => format!(
+            "retried     {} -> {} (verification failed)",
+            src.display(),
+            dest.display()
+        ),
+        Event::Rename { src, dest } => {
+            format!("renamed     {} -> {}", src.display(), dest.display())
+        }
+        Event::Delete { path, reason } => format!(
+            "deleted     {} ({})",
+            path.display(),
+            describe_delete_reason(reason)
+        ),
+        Event::Backup { path, backup } => {
+            format!("backed up   {} -> {}", path.display(), backup.display())
+        }
+        Event::Ignore { path } => format!("ignored     {}", path.display()),
+        Event::SkipMount { path } => format!("skipped     {} (other filesystem)", path.display()),
+        Event::Error { src, dest, message } => format!(
+            "failed      {} -> {} ({})",
+            src.map(|p| p.display().to_string()).unwrap_or_default(),
+            dest.map(|p| p.display().to_string()).unwrap_or_default(),
+            message
+        ),
+        Event::Plan {
+            src, dest, action, ..
+        } => describe_plan(*src, *dest, action),
+        _ => return None,
+    };
+
+    Some(line)
+}
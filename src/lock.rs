@@ -0,0 +1,247 @@
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::signals;
+
+const LOCK_DIR: &str = ".pcp";
+const LOCK_FILE_NAME: &str = ".pcp-lock.pcp";
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// advisory lock on the job state of a destination.
+/// the lock file holds the pid and host of the owning process
+/// and is removed again when the lock is dropped.
+pub struct DestinationLock {
+    path: PathBuf,
+}
+
+#[derive(Debug, PartialEq)]
+struct Owner {
+    pid: u32,
+    host: String,
+}
+
+impl DestinationLock {
+    /// take the lock for the destination.
+    /// if another job holds it, either wait for it to be released or fail right away.
+    /// returns `Ok(None)` if the job got interrupted while waiting.
+    pub fn acquire(dest_dir: impl AsRef<Path>, wait: bool) -> std::io::Result<Option<Self>> {
+        let path = dest_dir.as_ref().join(LOCK_DIR).join(LOCK_FILE_NAME);
+
+        let mut waiting = false;
+
+        loop {
+            // the previous owner removes the directory when it's done with it
+            std::fs::create_dir_all(path.parent().unwrap())?;
+
+            let owner = match try_create(&path) {
+                Ok(()) => return Ok(Some(DestinationLock { path })),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => read_owner(&path),
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            match owner {
+                Some(owner) if owner.is_stale() => {
+                    eprintln!(
+                        "Removing stale lock on {} held by pid {} on {}",
+                        dest_dir.as_ref().display(),
+                        owner.pid,
+                        owner.host
+                    );
+
+                    remove_stale_lock(&path, &owner)?;
+                    continue;
+                }
+                owner if !wait => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::WouldBlock,
+                        format!(
+                            "{} is locked by another pcp job{}. pass --wait-for-lock to wait for it",
+                            dest_dir.as_ref().display(),
+                            describe(owner.as_ref())
+                        ),
+                    ));
+                }
+                owner => {
+                    if !waiting {
+                        eprintln!(
+                            "Waiting for the lock on {} held by another pcp job{}",
+                            dest_dir.as_ref().display(),
+                            describe(owner.as_ref())
+                        );
+
+                        waiting = true;
+                    }
+
+                    if signals::interrupted() {
+                        return Ok(None);
+                    }
+
+                    std::thread::sleep(LOCK_POLL_INTERVAL);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for DestinationLock {
+    fn drop(&mut self) {
+        if let Err(e) = remove_lock_file(&self.path) {
+            eprintln!("Error releasing lock {}: {:?}", self.path.display(), e);
+        }
+    }
+}
+
+impl Owner {
+    fn current() -> Self {
+        Owner {
+            pid: std::process::id(),
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+        }
+    }
+
+    /// the content of a lock file, the pid and host on separate lines
+    fn parse(content: &str) -> Option<Self> {
+        let (pid, host) = content.split_once('\n')?;
+
+        Some(Owner {
+            pid: pid.trim().parse().ok().filter(|pid| *pid != 0)?,
+            host: host.trim().to_string(),
+        })
+    }
+
+    /// a lock is only known to be stale if it was taken on this host by a process
+    /// which no longer exists. locks from other hosts are always respected.
+    fn is_stale(&self) -> bool {
+        self.host == Owner::current().host && !process_exists(self.pid)
+    }
+}
+
+fn try_create(path: &Path) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+
+    let owner = Owner::current();
+    file.write_all(format!("{}\n{}", owner.pid, owner.host).as_bytes())?;
+    file.sync_data()
+}
+
+fn read_owner(path: &Path) -> Option<Owner> {
+    Owner::parse(&std::fs::read_to_string(path).ok()?)
+}
+
+/// remove a stale lock without touching a lock another job took in the meantime.
+/// the lock file is moved aside first and only removed if it's still the stale one
+fn remove_stale_lock(path: &Path, stale: &Owner) -> std::io::Result<()> {
+    let mut aside = path.as_os_str().to_os_string();
+    aside.push(format!(".stale-{}", std::process::id()));
+    let aside = PathBuf::from(aside);
+
+    match std::fs::rename(path, &aside) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        result => result?,
+    }
+
+    if read_owner(&aside).as_ref() != Some(stale) {
+        // put the fresh lock back, unless yet another job took the lock already
+        match std::fs::hard_link(&aside, path) {
+            Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+    }
+
+    remove_lock_file(&aside)
+}
+
+fn remove_lock_file(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+fn describe(owner: Option<&Owner>) -> String {
+    owner
+        .map(|owner| format!(" (pid {} on {})", owner.pid, owner.host))
+        .unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    // signal 0 only checks whether the process exists and may be signaled
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    // without a cheap way to check, assume the owner is still running
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner() {
+        assert_eq!(
+            Owner::parse("42\nhost\n"),
+            Some(Owner {
+                pid: 42,
+                host: "host".to_string()
+            })
+        );
+        assert_eq!(Owner::parse("42"), None);
+        assert_eq!(Owner::parse("0\nhost"), None);
+        assert_eq!(Owner::parse("pid\nhost"), None);
+
+        let current = Owner::current();
+        assert!(!current.is_stale());
+
+        // higher than any pid linux hands out
+        let gone = Owner {
+            pid: 999_999_999,
+            ..Owner::current()
+        };
+        assert_eq!(gone.is_stale(), cfg!(unix));
+
+        let elsewhere = Owner {
+            pid: 999_999_999,
+            host: format!("{}-elsewhere", current.host),
+        };
+        assert!(!elsewhere.is_stale());
+    }
+
+    #[test]
+    fn test_remove_stale_lock() {
+        let dir = std::env::temp_dir().join(format!("pcp-lock-{}", std::process::id()));
+        let path = dir.join(LOCK_DIR).join(LOCK_FILE_NAME);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let stale = Owner {
+            pid: 999_999_999,
+            ..Owner::current()
+        };
+
+        // a lock taken by another job after the stale one was seen is kept
+        std::fs::write(&path, format!("1\n{}", stale.host)).unwrap();
+        remove_stale_lock(&path, &stale).unwrap();
+        assert_eq!(read_owner(&path).map(|owner| owner.pid), Some(1));
+
+        std::fs::write(&path, format!("{}\n{}", stale.pid, stale.host)).unwrap();
+        remove_stale_lock(&path, &stale).unwrap();
+        assert!(!path.exists());
+
+        let lock = DestinationLock::acquire(&dir, false).unwrap().unwrap();
+        assert_eq!(read_owner(&path), Some(Owner::current()));
+        assert!(DestinationLock::acquire(&dir, false).is_err());
+        drop(lock);
+        assert!(!path.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,18 +1,38 @@
 #![allow(clippy::too_many_arguments)]
+mod backup;
+mod checksum;
 mod cli;
+mod config;
+mod dry_run;
+mod events;
+mod fan_out;
 mod file_operations;
+mod filter;
+mod ignore_files;
+mod itemize;
+mod lock;
 mod path_utils;
 mod program;
 mod progress;
 mod progress_bar;
+mod prompt;
+mod scan;
+mod signals;
+mod summary;
 
 use clap::Parser;
 use cli::Cli;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+fn main() {
+    let cli = Cli::try_parse().unwrap_or_else(|e| summary::exit_usage_error(e));
 
-    program::run(cli)?;
+    let cli = config::apply(cli).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        std::process::exit(summary::FATAL_EXIT_CODE);
+    });
 
-    Ok(())
+    if let Err(e) = program::run(cli) {
+        eprintln!("Error: {}", e);
+        std::process::exit(summary::FATAL_EXIT_CODE);
+    }
 }
@@ -3,6 +3,8 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use crate::progress::state_dir;
+
 pub fn get_path(path: impl AsRef<Path>) -> Result<PathBuf> {
     let path_str = path.as_ref().to_str().unwrap();
     let path = match path_str {
@@ -41,10 +43,149 @@ pub fn get_path(path: impl AsRef<Path>) -> Result<PathBuf> {
     Ok(path)
 }
 
+/// whether a path is a glob pattern. existing paths are always taken literally
+pub fn is_glob(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+
+    path.to_string_lossy().contains(['*', '?', '[']) && !path.exists()
+}
+
+/// expand a glob pattern like `a/*.mkv` or `a/**/*.mkv` to the matching paths.
+/// paths which aren't patterns are returned as is
+pub fn expand_glob(path: &Path) -> Result<Vec<PathBuf>> {
+    if !is_glob(path) {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let pattern = path.to_string_lossy();
+
+    let matches = glob::glob(&pattern)
+        .map_err(|e| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid pattern {}: {}", pattern, e),
+            )
+        })?
+        .filter_map(|entry| entry.ok())
+        .collect::<Vec<_>>();
+
+    if matches.is_empty() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("No files match {}", pattern),
+        ));
+    }
+
+    Ok(matches)
+}
+
+/// whether a source names the contents of a directory rather than the directory itself,
+/// like `src/` or `.`
+pub fn names_contents(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref().to_string_lossy();
+
+    path.ends_with(std::path::is_separator)
+        || path == "."
+        || path.ends_with("/.")
+        || path.ends_with(r"\.")
+}
+
+/// where a source ends up when copied to a destination, following cp.
+/// a source copied into a directory keeps its name, otherwise the destination
+/// is the copy itself
+pub fn target_path(
+    source_arg: &Path,
+    source: &Path,
+    destination_arg: &Path,
+    destination: PathBuf,
+    target_directory: bool,
+) -> PathBuf {
+    let into_directory = if source.is_dir() {
+        // a destination holding the state of an unfinished job is resumed in place
+        !names_contents(source_arg)
+            && (target_directory || (destination.is_dir() && !state_dir(&destination).exists()))
+    } else {
+        target_directory || destination.is_dir() || names_contents(destination_arg)
+    };
+
+    match source.file_name() {
+        Some(name) if into_directory => destination.join(name),
+        _ => destination,
+    }
+}
+
+/// the `n`th name of a file for --overwrite keep-both
+pub fn keep_both_path(pattern: &str, path: &Path, n: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    path.with_file_name(
+        pattern
+            .replace("{stem}", &stem)
+            .replace("{ext}", &ext)
+            .replace("{n}", &n.to_string()),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_expand_glob() {
+        let dir = std::env::temp_dir().join(format!("pcp-glob-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.mkv"), "").unwrap();
+        std::fs::write(dir.join("sub").join("b.mkv"), "").unwrap();
+        std::fs::write(dir.join("c.txt"), "").unwrap();
+
+        let flat = expand_glob(&dir.join("*.mkv")).unwrap();
+        let recursive = expand_glob(&dir.join("**").join("*.mkv")).unwrap();
+        let none = expand_glob(&dir.join("*.avi"));
+        let literal = expand_glob(&dir.join("c.txt")).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(flat, vec![dir.join("a.mkv")]);
+        assert_eq!(
+            recursive,
+            vec![dir.join("a.mkv"), dir.join("sub").join("b.mkv")]
+        );
+        assert_eq!(none.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(literal, vec![dir.join("c.txt")]);
+    }
+
+    #[test]
+    fn test_keep_both_path() {
+        let pattern = "{stem} ({n}){ext}";
+
+        assert_eq!(
+            keep_both_path(pattern, Path::new("/dcim/IMG_0001.jpg"), 1),
+            Path::new("/dcim/IMG_0001 (1).jpg")
+        );
+        assert_eq!(
+            keep_both_path(pattern, Path::new("/a/archive.tar.gz"), 2),
+            Path::new("/a/archive.tar (2).gz")
+        );
+        assert_eq!(
+            keep_both_path("{stem}_{n}{ext}", Path::new("/a/.profile"), 3),
+            Path::new("/a/.profile_3")
+        );
+    }
+
+    #[test]
+    fn test_names_contents() {
+        assert!(names_contents("src/"));
+        assert!(names_contents("."));
+        assert!(names_contents("src/."));
+        assert!(!names_contents("src"));
+        assert!(!names_contents(".cargo"));
+    }
+
     #[test]
     fn test_get_full_path() {
         let path = r"~\.cargo".to_string();
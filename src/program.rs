@@ -1,24 +1,42 @@
 use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
     io::{IsTerminal, Read},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
-use indicatif::MultiProgress;
 use rayon::{
     iter::{IntoParallelRefIterator, ParallelBridge, ParallelIterator},
     ThreadPoolBuilder,
 };
 
-use walkdir::WalkDir;
+use walkdir::DirEntry;
 
 use crate::{
-    cli::Cli,
-    file_operations::{copy_files_par, delete_file, move_files_par},
-    path_utils::get_path,
-    progress::{cleanup, CompletionTracker},
+    checksum,
+    cli::{Cli, OverwriteMode, ProgressMode},
+    dry_run,
+    events::{self, DeleteReason, Event, SkipReason},
+    fan_out::{fan_out_files_par, FanOutDestination},
+    file_operations::{
+        back_up_file, copy_files_par, delete_file, display_paths, move_files_par, purge_candidates,
+    },
+    filter::Filter,
+    itemize,
+    lock::DestinationLock,
+    path_utils::{expand_glob, get_path, is_glob, target_path},
+    progress::{cleanup, is_completed, CompletionTracker},
+    progress_bar::{JobProgress, Theme},
+    prompt,
+    scan::{check_sources, resolve_conflicts, Scan},
+    signals, summary,
 };
 
-pub fn run(cli: Cli) -> std::io::Result<()> {
+pub fn run(mut cli: Cli) -> std::io::Result<()> {
+    if cli.interactive {
+        cli.overwrite = OverwriteMode::Interactive;
+    }
+
     if cli.buf_size.to_bytes() == 0 {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidInput,
@@ -26,14 +44,30 @@ pub fn run(cli: Cli) -> std::io::Result<()> {
         ));
     }
 
+    if cli.purge && cli.overwrite == OverwriteMode::KeepBoth {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "--purge would delete the files renamed by --overwrite keep-both",
+        ));
+    }
+
+    let filter = Filter::from_cli(&cli)?;
+
     let mut stdin = std::io::stdin();
     let mut input = String::new();
 
     let has_stdin = !stdin.is_terminal() && stdin.read_to_string(&mut input)? != 0;
 
-    if !has_stdin && cli.destinations.is_empty() {
+    let (source_args, destination_args) = cli_paths(&cli);
+
+    if !has_stdin && source_args.is_empty() {
+        eprintln!("You must specify at least 1 source path");
+        std::process::exit(summary::FATAL_EXIT_CODE);
+    }
+
+    if !has_stdin && destination_args.is_empty() {
         eprintln!("You must specify at least 1 destination path");
-        std::process::exit(1);
+        std::process::exit(summary::FATAL_EXIT_CODE);
     }
 
     ThreadPoolBuilder::new()
@@ -41,142 +75,492 @@ pub fn run(cli: Cli) -> std::io::Result<()> {
         .build_global()
         .unwrap();
 
+    signals::install()?;
+
+    // the answers to interactive prompts are read from the terminal as well
+    if !has_stdin && cli.overwrite != OverwriteMode::Interactive {
+        signals::listen_for_pause_key();
+    }
+
+    let max_file_bars = cli
+        .max_file_bars
+        .map(|m| m.get())
+        .unwrap_or_else(rayon::current_num_threads);
+
+    events::init(cli.json, cli.json_file.as_deref())?;
+
+    if matches!(
+        cli.overwrite,
+        OverwriteMode::Checksum | OverwriteMode::KeepBoth
+    ) {
+        checksum::init();
+    }
+
+    let progress_mode = match (cli.json || cli.dry_run, &cli.progress_mode) {
+        (true, _) => ProgressMode::None,
+        (_, ProgressMode::Auto) if std::io::stderr().is_terminal() => ProgressMode::Bars,
+        (_, ProgressMode::Auto) => ProgressMode::Plain,
+        (_, mode) => mode.clone(),
+    };
+
+    if cli.no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+
+    let job_progress = JobProgress::new(
+        max_file_bars,
+        &progress_mode,
+        cli.plain_interval,
+        Theme::from_cli(&cli),
+    )
+    .map_err(std::io::Error::other)?;
+
+    // a dry run prints its plan as itemized lines, unless it's emitted as json
+    itemize::init(
+        cli.itemize || (cli.dry_run && !cli.json),
+        cli.itemize_file.as_deref(),
+        job_progress.multi_progress(),
+    )?;
+
+    if cli.overwrite == OverwriteMode::Interactive && !cli.dry_run {
+        prompt::init(job_progress.multi_progress())?;
+    }
+
     if !has_stdin {
-        let source = get_path(cli.source.as_ref().ok_or(std::io::ErrorKind::Other)?)?;
-        let destinations = cli
-            .destinations
+        let sources = source_args
             .iter()
-            .map(get_path)
-            .collect::<Result<Vec<_>, _>>()?;
+            .map(|source_arg| {
+                let matches = expand_glob(&get_path(source_arg)?)?;
+                Ok(matches.into_iter().map(move |source| (*source_arg, source)))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
 
-        if destinations.contains(&source) {
-            eprintln!("Source and Destination paths are the same");
-            std::process::exit(1);
-        }
+        // like cp, multiple sources are always copied into the destinations
+        let into_directory = cli.target_directory.is_some() || sources.len() > 1;
+
+        let operations = sources
+            .into_iter()
+            .map(|(source_arg, source)| {
+                let destinations = destination_args
+                    .iter()
+                    .map(|destination_arg| {
+                        Ok(target_path(
+                            source_arg,
+                            &source,
+                            destination_arg,
+                            get_path(destination_arg)?,
+                            into_directory,
+                        ))
+                    })
+                    .collect::<std::io::Result<Vec<_>>>()?;
+
+                if destinations.contains(&source) {
+                    eprintln!("Source and Destination paths are the same");
+                    std::process::exit(summary::FATAL_EXIT_CODE);
+                }
 
-        let multi_progress = MultiProgress::new();
-        multi_progress.set_move_cursor(true);
-        handle_multiple_files(cli, source, destinations, &multi_progress)?;
+                Ok((source, destinations))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        run_job(&cli, operations, &filter, &job_progress)?;
     } else {
         let lines = input.lines();
 
-        let multi_progress = MultiProgress::new();
-        multi_progress.set_move_cursor(true);
-
         lines.par_bridge().try_for_each(|line| {
             if line.trim_start().starts_with('#') {
                 return Ok(());
             }
 
-            let (source, destinations) = parse_operation(line)?;
-            handle_multiple_files(cli.clone(), source, destinations, &multi_progress)
+            let operations = parse_operation(line)?;
+            run_job(&cli, operations, &filter, &job_progress)
         })?;
     }
 
-    Ok(())
+    if cli.dry_run {
+        let plan_summary = events::plan_summary();
+        events::emit(Event::PlanSummary(plan_summary.clone()));
+
+        if !cli.json {
+            summary::print_plan(&plan_summary);
+        }
+
+        return Ok(());
+    }
+
+    // hashes of an interrupted run are still valid
+    if let Err(e) = checksum::save() {
+        eprintln!("Error saving checksum cache: {}", e);
+    }
+
+    let interrupted = signals::interrupted();
+    let summary = events::summary(interrupted);
+    events::emit(Event::JobSummary(summary.clone()));
+
+    if interrupted {
+        job_progress.clear()?;
+        signals::exit_interrupted();
+    }
+
+    job_progress.finish();
+
+    if !cli.json {
+        summary::print(&summary);
+    }
+
+    match summary::exit_code(&summary) {
+        summary::SUCCESS_EXIT_CODE => Ok(()),
+        code => std::process::exit(code),
+    }
 }
 
-fn parse_operation(line: &str) -> std::io::Result<(PathBuf, Vec<PathBuf>)> {
+/// the sources and destinations passed as arguments.
+/// with --source all positional arguments are destinations,
+/// with --target-directory all positional arguments are sources
+fn cli_paths(cli: &Cli) -> (Vec<&PathBuf>, Vec<&PathBuf>) {
+    let positional = cli.source.iter().chain(&cli.destinations);
+
+    if !cli.sources.is_empty() {
+        (
+            cli.sources.iter().collect(),
+            cli.target_directory.iter().chain(positional).collect(),
+        )
+    } else if let Some(target_directory) = &cli.target_directory {
+        (positional.collect(), vec![target_directory])
+    } else {
+        (
+            cli.source.iter().collect(),
+            cli.destinations.iter().collect(),
+        )
+    }
+}
+
+/// a line of `source:destination[:destination...]`, copied like the same arguments
+/// on the command line. a source pattern like `a/*.mkv` copies each match into the
+/// destinations, keeping its name
+fn parse_operation(line: &str) -> std::io::Result<Vec<(PathBuf, Vec<PathBuf>)>> {
     let mut split = line.split(':');
-    let source = split.next().ok_or(std::io::ErrorKind::Other)?;
-    let destinations = split.collect::<Vec<_>>();
+    let source_arg = Path::new(split.next().ok_or(std::io::ErrorKind::Other)?.trim());
+    let destination_args = split.map(|d| Path::new(d.trim())).collect::<Vec<_>>();
 
-    if destinations.is_empty() {
+    if destination_args.is_empty() {
         eprintln!("You must specify at least 1 destination path");
-        std::process::exit(1);
+        std::process::exit(summary::FATAL_EXIT_CODE);
     }
 
-    let source = get_path(source.trim())?;
-    let destinations = destinations
-        .iter()
-        .map(|d| get_path(d.trim()))
+    let source = get_path(source_arg)?;
+    let into_directory = is_glob(&source);
+
+    let operations = expand_glob(&source)?
+        .into_iter()
+        .map(|source| {
+            let destinations = destination_args
+                .iter()
+                .map(|destination_arg| {
+                    Ok(target_path(
+                        source_arg,
+                        &source,
+                        destination_arg,
+                        get_path(destination_arg)?,
+                        into_directory,
+                    ))
+                })
+                .collect::<std::io::Result<Vec<_>>>()?;
+
+            Ok((source, destinations))
+        })
         .collect::<std::io::Result<Vec<_>>>()?;
 
-    Ok((source, destinations))
+    Ok(operations)
 }
 
-fn handle_multiple_files(
-    cli: Cli,
-    source: PathBuf,
-    destinations: Vec<PathBuf>,
-    multi_progress: &MultiProgress,
+/// scan all sources first, so conflicts between them are known before anything is copied
+fn run_job(
+    cli: &Cli,
+    operations: Vec<(PathBuf, Vec<PathBuf>)>,
+    filter: &Filter,
+    job_progress: &JobProgress,
 ) -> std::io::Result<()> {
-    let files = WalkDir::new(&source)
+    check_sources(operations.iter().map(|(source, _)| source.as_path()))?;
+
+    let mut scans = operations
         .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| e.path().is_file())
+        .map(|(source, destinations)| Scan::new(source, destinations, filter, job_progress))
         .collect::<Vec<_>>();
 
-    for destination in &destinations {
-        let mut tracker = CompletionTracker::open(destination, cli.use_progress)?;
-        let completed = tracker.read();
+    for scan in &scans {
+        events::emit(Event::Scan {
+            source: &scan.source,
+            destinations: &scan.destinations,
+            files: scan.files.len() as u64,
+            bytes: files_size(&scan.files),
+        });
+    }
 
-        let files = files
-            .clone()
-            .into_iter()
-            .filter(|e| !completed.contains(e.file_name()))
-            .collect();
+    resolve_conflicts(&mut scans, &cli.on_conflict)?;
 
-        if cli.move_files {
-            if destinations.len() == 1 && std::fs::rename(&source, destination).is_ok() {
-                println!("Renamed {} -> {}", source.display(), destination.display());
-                return Ok(());
+    // every path provided by the job, so purging one destination never removes
+    // files another source copies into it
+    let targets = scans.iter().flat_map(Scan::targets).collect::<HashSet<_>>();
+
+    if cli.dry_run {
+        let mut purged = HashSet::new();
+
+        for scan in &scans {
+            dry_run::plan(cli, scan, filter)?;
+
+            if !cli.purge {
+                continue;
             }
 
-            move_files_par(&cli, &source, destination, &tracker, &files, multi_progress)?;
-        } else {
-            copy_files_par(&cli, &source, destination, &tracker, &files, multi_progress)?;
+            // sources copied into the same destination share a single purge
+            for destination in &scan.destinations {
+                if purged.insert(destination) {
+                    dry_run::plan_purge(cli, &scan.source, destination, &targets, filter);
+                }
+            }
         }
 
-        if cli.purge {
-            let dest_files = WalkDir::new(destination)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| e.path().is_file())
-                .collect::<Vec<_>>();
+        return Ok(());
+    }
 
-            dest_files.par_iter().for_each(|dest_file| {
-                let dest_path = dest_file.path().strip_prefix(destination);
+    for scan in &scans {
+        job_progress.add_work(
+            (scan.files.len() * scan.destinations.len()) as u64,
+            files_size(&scan.files) * scan.destinations.len() as u64,
+        );
+    }
 
-                if !files
-                    .iter()
-                    .any(|src_file| src_file.path().strip_prefix(&source) == dest_path)
+    // every destination is locked and its .pcp/ state opened once for all sources,
+    // so an interrupted job is resumed as a whole
+    let destinations = open_destinations(cli, &scans)?;
+
+    for scan in &scans {
+        handle_multiple_files(cli, scan, &destinations, filter, job_progress)?;
+    }
+
+    if signals::interrupted() {
+        // keep the .pcp/ state around so the job can be resumed
+        return Ok(());
+    }
+
+    if cli.purge {
+        let mut purged = HashSet::new();
+
+        // sources copied into the same destination share a single purge
+        for scan in &scans {
+            for destination in &scan.destinations {
+                if destinations.contains_key(scan.state_root(destination))
+                    && purged.insert(destination)
                 {
-                    let (src_str, dest_str) = if cli.absolute_paths {
-                        (source.to_str().unwrap(), dest_file.path().to_str().unwrap())
-                    } else {
-                        (
-                            source
-                                .strip_prefix(
-                                    std::env::current_dir().expect("Error getting current dir"),
-                                )
-                                .unwrap_or(dest_file.path())
-                                .to_str()
-                                .unwrap(),
-                            dest_file
-                                .path()
-                                .strip_prefix(
-                                    std::env::current_dir().expect("Error getting current dir"),
-                                )
-                                .unwrap_or(dest_file.path())
-                                .to_str()
-                                .unwrap(),
-                        )
-                    };
-
-                    eprintln!("Deleting: {}. Not found in source: {}", dest_str, src_str);
-
-                    delete_file(dest_file.path())
+                    purge(cli, &scan.source, destination, &targets, filter);
                 }
-            });
+            }
         }
+    }
 
-        tracker.remove()?;
+    for (state_root, destination) in destinations {
+        destination.tracker.remove()?;
+        drop(destination.lock);
 
         if cli.use_progress {
-            cleanup(destination)?;
+            cleanup(state_root)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// a destination directory of a job, locked along with its .pcp/ state
+/// for all sources copied into it
+struct JobDestination {
+    lock: Option<DestinationLock>,
+    tracker: CompletionTracker,
+    /// files completed by a previous run of the job
+    completed: HashSet<OsString>,
+}
+
+/// lock and open the state of every destination of the job, by the directory holding it.
+/// destinations which couldn't be locked are left out
+fn open_destinations(
+    cli: &Cli,
+    scans: &[Scan],
+) -> std::io::Result<HashMap<PathBuf, JobDestination>> {
+    let mut destinations = HashMap::new();
+    let mut locked_out = HashSet::new();
+
+    for scan in scans {
+        for destination in &scan.destinations {
+            let state_root = scan.state_root(destination);
+
+            if destinations.contains_key(state_root)
+                || locked_out.contains(state_root)
+                || signals::interrupted()
+            {
+                continue;
+            }
+
+            let lock = if cli.use_progress {
+                let Some(lock) = DestinationLock::acquire(state_root, cli.wait_for_lock)? else {
+                    locked_out.insert(state_root.to_path_buf());
+                    continue;
+                };
+
+                Some(lock)
+            } else {
+                None
+            };
+
+            let mut tracker = CompletionTracker::open(
+                state_root,
+                cli.use_progress,
+                cli.checkpoint_interval.clone(),
+            )?;
+            let completed = tracker.read();
+
+            destinations.insert(
+                state_root.to_path_buf(),
+                JobDestination {
+                    lock,
+                    tracker,
+                    completed,
+                },
+            );
+        }
+    }
+
+    Ok(destinations)
+}
+
+fn handle_multiple_files(
+    cli: &Cli,
+    scan: &Scan,
+    job_destinations: &HashMap<PathBuf, JobDestination>,
+    filter: &Filter,
+    job_progress: &JobProgress,
+) -> std::io::Result<()> {
+    let Scan {
+        source,
+        destinations,
+        files,
+        walked,
+    } = scan;
+
+    let total_size = files_size(files);
+
+    // the destinations which could be locked, along with their .pcp/ state
+    let mut opened = vec![];
+
+    for destination in destinations {
+        let Some(JobDestination {
+            tracker, completed, ..
+        }) = job_destinations.get(scan.state_root(destination))
+        else {
+            signals::mark_remaining(source, destination);
+            continue;
+        };
+
+        let state_root = scan.state_root(destination);
+
+        let (completed_files, remaining): (Vec<_>, Vec<_>) = files.iter().cloned().partition(|e| {
+            scan.target(destination, e)
+                .is_some_and(|target| is_completed(completed, state_root, target))
+        });
+
+        // files completed by a previous run count as done
+        job_progress.skip_files(
+            completed_files.len() as u64,
+            total_size - files_size(&remaining),
+        );
+
+        for completed_file in &completed_files {
+            if let Some(target) = scan.target(destination, completed_file) {
+                events::emit(Event::Skip {
+                    src: completed_file.path(),
+                    dest: &target,
+                    reason: SkipReason::AlreadyCompleted,
+                });
+            }
+        }
+
+        opened.push((destination, tracker, completed, remaining));
+    }
+
+    if opened.len() > 1 {
+        // every file is read once and written to all destinations at the same time
+        let fan_out_destinations = opened
+            .iter()
+            .map(|(destination, tracker, completed, _)| FanOutDestination {
+                root: destination,
+                tracker,
+                completed,
+            })
+            .collect::<Vec<_>>();
+
+        fan_out_files_par(cli, source, &fan_out_destinations, files, job_progress);
+    } else if let Some((destination, tracker, _, remaining)) = opened.first() {
+        if cli.move_files {
+            // renaming the whole source would take excluded files along
+            if destinations.len() == 1
+                && filter.is_empty()
+                && files.len() == walked.len()
+                && std::fs::rename(source, destination).is_ok()
+            {
+                job_progress.skip_files(remaining.len() as u64, files_size(remaining));
+
+                events::emit(Event::Rename {
+                    src: source,
+                    dest: destination,
+                });
+
+                if !cli.json {
+                    println!("Renamed {} -> {}", source.display(), destination.display());
+                }
+                return Ok(());
+            }
+
+            move_files_par(cli, source, destination, tracker, remaining, job_progress)?;
+        } else {
+            copy_files_par(cli, source, destination, tracker, remaining, job_progress)?;
         }
     }
 
     Ok(())
 }
+
+/// delete or back up the files of a destination which aren't provided by the job
+fn purge(
+    cli: &Cli,
+    source: &Path,
+    destination: &Path,
+    targets: &HashSet<PathBuf>,
+    filter: &Filter,
+) {
+    let dest_files = purge_candidates(cli, source, destination, targets, filter);
+
+    dest_files.par_iter().for_each(|dest_file| {
+        let (src_str, dest_str) = display_paths(cli, source, dest_file.path());
+
+        if cli.backup_purged {
+            eprintln!("Backing up: {}. Not found in source: {}", dest_str, src_str);
+
+            back_up_file(cli, destination, dest_file.path())
+        } else {
+            eprintln!("Deleting: {}. Not found in source: {}", dest_str, src_str);
+
+            delete_file(dest_file.path(), DeleteReason::Purged)
+        }
+    });
+}
+
+fn files_size(files: &[DirEntry]) -> u64 {
+    files
+        .iter()
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
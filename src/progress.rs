@@ -1,14 +1,17 @@
 use std::{
     collections::{HashMap, HashSet},
-    ffi::{OsStr, OsString},
+    ffi::OsString,
     fs::{File, OpenOptions},
     io::{Read, Seek, Write},
     path::{Path, PathBuf},
     sync::{Mutex, RwLock},
+    time::Instant,
 };
 
 use os_str_bytes::{OsStrBytes, OsStringBytes};
 
+use crate::cli::CheckpointInterval;
+
 const PROGRESS_DIR: &str = ".pcp";
 const COMPLETED_FILE_NAME: &str = ".pcp-completed.pcp";
 const PROGRESS_EXT: &str = ".pcp";
@@ -20,15 +23,32 @@ pub struct CompletionTracker {
     dest: Option<PathBuf>,
     completed_path: Option<PathBuf>,
     progress_files: RwLock<HashMap<PathBuf, ProgressFile>>,
+    checkpoint_interval: CheckpointInterval,
 }
 
 #[derive(Debug)]
 struct ProgressFile {
     file: Mutex<File>,
+    last_checkpoint: Mutex<Checkpoint>,
     pub current: u64,
     pub total: u64,
 }
 
+#[derive(Debug)]
+struct Checkpoint {
+    bytes: u64,
+    at: Instant,
+}
+
+impl Checkpoint {
+    fn new(bytes: u64) -> Self {
+        Checkpoint {
+            bytes,
+            at: Instant::now(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Progress {
     pub current: u64,
@@ -45,13 +65,18 @@ impl From<&ProgressFile> for Progress {
 }
 
 impl CompletionTracker {
-    pub fn open(dest_dir: impl AsRef<Path>, enabled: bool) -> std::io::Result<CompletionTracker> {
+    pub fn open(
+        dest_dir: impl AsRef<Path>,
+        enabled: bool,
+        checkpoint_interval: CheckpointInterval,
+    ) -> std::io::Result<CompletionTracker> {
         if !enabled {
             return Ok(CompletionTracker {
                 completed_file: None,
                 dest: None,
                 completed_path: None,
                 progress_files: RwLock::new(HashMap::new()),
+                checkpoint_interval,
             });
         }
 
@@ -74,9 +99,19 @@ impl CompletionTracker {
             dest: Some(dest_dir.to_path_buf()),
             completed_path: Some(completed_file_path),
             progress_files: RwLock::new(HashMap::new()),
+            checkpoint_interval,
         })
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.dest.is_some()
+    }
+
+    /// the directory the state is kept for, None if progress isn't tracked
+    pub fn root(&self) -> Option<&Path> {
+        self.dest.as_deref()
+    }
+
     pub fn read(&mut self) -> HashSet<OsString> {
         let Some(file) = &mut self.completed_file else {
             return HashSet::new();
@@ -95,10 +130,7 @@ impl CompletionTracker {
             return HashSet::new();
         }
 
-        buf.split(|b| *b == b'\n')
-            .filter(|line| !line.is_empty())
-            .map(|line| OsString::from_io_vec(line.to_vec()).unwrap())
-            .collect::<HashSet<_>>()
+        parse_completed(&buf)
     }
 
     pub fn add_completed(&self, completed: impl AsRef<Path>) -> std::io::Result<()> {
@@ -129,14 +161,14 @@ impl CompletionTracker {
 
     pub fn add_progress_file(
         &self,
-        file_name: impl AsRef<OsStr>,
+        path: impl AsRef<Path>,
         total_bytes: u64,
     ) -> std::io::Result<Option<Progress>> {
         let Some(dest) = &self.dest else {
             return Ok(None);
         };
 
-        let file_path = get_progress_file_path(dest, file_name);
+        let file_path = get_progress_file_path(dest, path);
 
         if std::fs::exists(&file_path)? {
             let open = OpenOptions::new().read(true).write(true).open(&file_path);
@@ -146,10 +178,12 @@ impl CompletionTracker {
                     let content = &mut String::new();
                     file.read_to_string(content)?;
                     let (current, total) = content.split_once('\n').unwrap();
+                    let current = current.trim_end().parse().unwrap();
 
                     let progress_file = ProgressFile {
                         file: Mutex::new(file),
-                        current: current.trim_end().parse().unwrap(),
+                        last_checkpoint: Mutex::new(Checkpoint::new(current)),
+                        current,
                         total: total.parse().unwrap(),
                     };
 
@@ -186,6 +220,7 @@ impl CompletionTracker {
 
         let progress_file = ProgressFile {
             file: Mutex::new(file),
+            last_checkpoint: Mutex::new(Checkpoint::new(0)),
             current: 0,
             total: total_bytes,
         };
@@ -204,16 +239,35 @@ impl CompletionTracker {
         Ok(Some(progress))
     }
 
+    /// write the progress of a file if the checkpoint interval has elapsed
     pub fn write_progress(
         &self,
-        file_name: impl AsRef<OsStr>,
+        path: impl AsRef<Path>,
         current_bytes: u64,
+    ) -> std::io::Result<()> {
+        self.checkpoint(path, current_bytes, false)
+    }
+
+    /// write the progress of a file regardless of the checkpoint interval
+    pub fn flush_progress(
+        &self,
+        path: impl AsRef<Path>,
+        current_bytes: u64,
+    ) -> std::io::Result<()> {
+        self.checkpoint(path, current_bytes, true)
+    }
+
+    fn checkpoint(
+        &self,
+        path: impl AsRef<Path>,
+        current_bytes: u64,
+        force: bool,
     ) -> std::io::Result<()> {
         let Some(dest) = &self.dest else {
             return Ok(());
         };
 
-        let file_path = get_progress_file_path(dest, file_name);
+        let file_path = get_progress_file_path(dest, path);
 
         let read = self.progress_files.read();
         let hash_map = read.expect("Failed to obtain read access");
@@ -222,6 +276,25 @@ impl CompletionTracker {
             .get(&file_path)
             .ok_or(std::io::ErrorKind::NotFound)?;
 
+        let mut last_checkpoint = progress
+            .last_checkpoint
+            .lock()
+            .expect("Failed to lock checkpoint");
+
+        let due = match &self.checkpoint_interval {
+            CheckpointInterval::EveryChunk => true,
+            CheckpointInterval::Bytes(size) => {
+                current_bytes.saturating_sub(last_checkpoint.bytes) >= size.to_bytes() as u64
+            }
+            CheckpointInterval::Time(duration) => last_checkpoint.at.elapsed() >= *duration,
+        };
+
+        if !force && !due {
+            return Ok(());
+        }
+
+        *last_checkpoint = Checkpoint::new(current_bytes);
+
         let mut file = progress.file.lock().expect("Failed to lock file");
         let data = current_bytes.to_string();
         let bytes = data.as_bytes();
@@ -233,12 +306,12 @@ impl CompletionTracker {
         Ok(())
     }
 
-    pub fn remove_progress_file(&self, file_name: impl AsRef<OsStr>) -> std::io::Result<()> {
+    pub fn remove_progress_file(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
         let Some(dest) = &self.dest else {
             return Ok(());
         };
 
-        let file_path = get_progress_file_path(dest, file_name);
+        let file_path = get_progress_file_path(dest, path);
 
         self.progress_files
             .write()
@@ -249,12 +322,57 @@ impl CompletionTracker {
     }
 }
 
+/// the directory holding the job state of a destination
+pub fn state_dir(dest_dir: impl AsRef<Path>) -> PathBuf {
+    dest_dir.as_ref().join(PROGRESS_DIR)
+}
+
+/// read the completed files of a destination without creating any state
+pub fn read_completed(dest_dir: impl AsRef<Path>) -> HashSet<OsString> {
+    let completed_file_path = dest_dir
+        .as_ref()
+        .join(PROGRESS_DIR)
+        .join(COMPLETED_FILE_NAME);
+
+    match std::fs::read(completed_file_path) {
+        Ok(buf) => parse_completed(&buf),
+        Err(_) => HashSet::new(),
+    }
+}
+
+/// whether a previous run completed a file, looked up by its path relative to the destination
+pub fn is_completed(
+    completed: &HashSet<OsString>,
+    dest_dir: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+) -> bool {
+    path.as_ref()
+        .strip_prefix(dest_dir)
+        .is_ok_and(|relative| completed.contains(relative.as_os_str()))
+}
+
+/// whether a previous run left a partially written file to resume
+pub fn has_progress(dest_dir: impl AsRef<Path>, path: impl AsRef<Path>) -> bool {
+    get_progress_file_path(dest_dir, path).exists()
+}
+
+fn parse_completed(buf: &[u8]) -> HashSet<OsString> {
+    buf.split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| OsString::from_io_vec(line.to_vec()).unwrap())
+        .collect::<HashSet<_>>()
+}
+
 pub fn cleanup(dest: impl AsRef<Path>) -> std::io::Result<()> {
     let progress_dir_path = dest.as_ref().join(PROGRESS_DIR);
 
     if let Err(e) = std::fs::remove_dir(progress_dir_path) {
-        if e.kind() == std::io::ErrorKind::NotFound {
-            // its fine if the directory doesn't exist
+        // its fine if the directory doesn't exist, or if the next job waiting
+        // for the lock already took it over
+        if matches!(
+            e.kind(),
+            std::io::ErrorKind::NotFound | std::io::ErrorKind::DirectoryNotEmpty
+        ) {
             return Ok(());
         }
 
@@ -264,13 +382,62 @@ pub fn cleanup(dest: impl AsRef<Path>) -> std::io::Result<()> {
     Ok(())
 }
 
-fn get_progress_file_name(file_name: impl AsRef<OsStr>) -> OsString {
-    let mut progress_file_name = file_name.as_ref().to_os_string();
+/// the progress file name of a file, from its path relative to the destination so
+/// files with the same name in different directories don't share progress
+fn get_progress_file_name(relative: &Path) -> OsString {
+    let mut escaped = Vec::new();
+    for byte in relative.as_os_str().to_io_bytes().unwrap_or_default() {
+        match byte {
+            b'%' => escaped.extend_from_slice(b"%25"),
+            b'/' => escaped.extend_from_slice(b"%2F"),
+            b'\\' => escaped.extend_from_slice(b"%5C"),
+            _ => escaped.push(*byte),
+        }
+    }
+
+    let mut progress_file_name = OsString::from_io_vec(escaped).unwrap_or_default();
     progress_file_name.push(PROGRESS_EXT);
     progress_file_name
 }
 
-fn get_progress_file_path(dest: impl AsRef<Path>, file_name: impl AsRef<OsStr>) -> PathBuf {
-    let progress_file_name = get_progress_file_name(file_name);
-    dest.as_ref().join(PROGRESS_EXT).join(progress_file_name)
+fn get_progress_file_path(dest: impl AsRef<Path>, path: impl AsRef<Path>) -> PathBuf {
+    let dest = dest.as_ref();
+    let path = path.as_ref();
+    let relative = path.strip_prefix(dest).unwrap_or(path);
+    let progress_file_name = get_progress_file_name(relative);
+    dest.join(PROGRESS_EXT).join(progress_file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_file_path_is_relative() {
+        let dest = Path::new("/dst");
+
+        assert_eq!(
+            get_progress_file_path(dest, dest.join("a.txt")),
+            dest.join(".pcp/a.txt.pcp")
+        );
+        assert_eq!(
+            get_progress_file_path(dest, dest.join("sub/a.txt")),
+            dest.join(".pcp/sub%2Fa.txt.pcp")
+        );
+        assert_eq!(
+            get_progress_file_path(dest, dest.join("sub%2Fa.txt")),
+            dest.join(".pcp/sub%252Fa.txt.pcp")
+        );
+    }
+
+    #[test]
+    fn test_is_completed() {
+        let completed = parse_completed(b"a.txt\nsub/b.txt\n");
+        let dest = Path::new("/dst");
+
+        assert!(is_completed(&completed, dest, dest.join("a.txt")));
+        assert!(is_completed(&completed, dest, dest.join("sub/b.txt")));
+        assert!(!is_completed(&completed, dest, dest.join("b.txt")));
+        assert!(!is_completed(&completed, dest, dest.join("other/a.txt")));
+    }
 }
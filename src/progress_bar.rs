@@ -1,25 +1,426 @@
-use std::time::Duration;
+use std::{
+    fmt::Display,
+    ops::Deref,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use indicatif::{style::TemplateError, ProgressBar, ProgressStyle};
+use indicatif::{
+    style::TemplateError, HumanBytes, HumanDuration, MultiProgress, ProgressBar,
+    ProgressDrawTarget, ProgressStyle,
+};
 
-pub fn create_progress_bar(total_size: u64) -> Result<ProgressBar, TemplateError> {
+use crate::{
+    cli::{Cli, ProgressMode},
+    signals,
+};
+
+pub const DEFAULT_PROGRESS_TEMPLATE: &str = "{percent:3}% [{bar:.cyan/blue}] {msg} {bytes_per_sec:.green} {bytes:.yellow}/{total_bytes:.magenta} ({eta:.cyan})";
+pub const DEFAULT_VERIFY_TEMPLATE: &str = "{spinner:1} {percent:3}% verifying {msg}";
+
+/// width kept free for the rest of the default template when compacting messages
+const COMPACT_RESERVED_WIDTH: usize = 60;
+const COMPACT_MIN_WIDTH: usize = 20;
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+
+/// how the per-file bars look
+#[derive(Debug, Clone)]
+pub struct Theme {
+    pub progress_template: String,
+    pub verify_template: String,
+    /// truncate the paths in the middle so the bars fit on one line
+    pub compact: bool,
+}
+
+/// progress display for the whole job.
+/// holds a top-level bar for all files and bytes across all destinations and only shows
+/// a bounded number of per-file bars at once. the per-file bars are removed once finished.
+/// in plain mode nothing is drawn, instead lines are printed which are fit for log files.
+pub struct JobProgress {
+    multi_progress: MultiProgress,
+    job_bar: ProgressBar,
+    files_total: Arc<AtomicU64>,
+    files_done: Arc<AtomicU64>,
+    visible_bars: Arc<AtomicUsize>,
+    max_visible_bars: usize,
+    plain: bool,
+    theme: Theme,
+}
+
+/// a per-file bar which is removed from the display when dropped
+pub struct FileBar {
+    bar: ProgressBar,
+    multi_progress: Option<MultiProgress>,
+    visible_bars: Arc<AtomicUsize>,
+}
+
+impl JobProgress {
+    /// the mode must be resolved already, `ProgressMode::Auto` is treated like bars.
+    /// in plain mode the job progress is printed every `plain_interval`
+    pub fn new(
+        max_visible_bars: usize,
+        mode: &ProgressMode,
+        plain_interval: Duration,
+        theme: Theme,
+    ) -> Result<Self, TemplateError> {
+        // fail early on invalid user templates instead of for every file
+        ProgressStyle::default_bar().template(&theme.progress_template)?;
+        ProgressStyle::default_bar().template(&theme.verify_template)?;
+
+        let multi_progress = match mode {
+            ProgressMode::Auto | ProgressMode::Bars => MultiProgress::new(),
+            ProgressMode::Plain | ProgressMode::None => {
+                MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+            }
+        };
+
+        multi_progress.set_move_cursor(true);
+
+        let job_bar = multi_progress.add(create_job_bar(0)?);
+
+        let job_progress = JobProgress {
+            multi_progress,
+            job_bar,
+            files_total: Arc::new(AtomicU64::new(0)),
+            files_done: Arc::new(AtomicU64::new(0)),
+            visible_bars: Arc::new(AtomicUsize::new(0)),
+            max_visible_bars,
+            plain: *mode == ProgressMode::Plain,
+            theme,
+        };
+
+        job_progress.update_message();
+
+        if job_progress.plain {
+            job_progress.spawn_plain_reporter(plain_interval);
+        }
+
+        job_progress.spawn_pause_watcher();
+
+        Ok(job_progress)
+    }
+
+    pub fn multi_progress(&self) -> MultiProgress {
+        self.multi_progress.clone()
+    }
+
+    pub fn scan_spinner(&self, source: &Path) -> ProgressBar {
+        let spinner = self.multi_progress.add(create_scan_spinner().unwrap());
+        spinner.set_message(format!("scanning {}", source.display()));
+        spinner
+    }
+
+    pub fn finish_scan(&self, spinner: ProgressBar) {
+        spinner.finish_and_clear();
+        self.multi_progress.remove(&spinner);
+
+        self.log(format!(
+            "{}: found {} files",
+            spinner.message(),
+            spinner.position()
+        ));
+    }
+
+    pub fn progress_bar(&self, total_size: u64) -> FileBar {
+        self.add_bar(create_progress_bar(total_size, &self.theme.progress_template).unwrap())
+    }
+
+    pub fn verify_bar(&self, total_size: u64) -> FileBar {
+        self.add_bar(create_verify_bar(total_size, &self.theme.verify_template).unwrap())
+    }
+
+    /// the message shown on the per-file bars
+    pub fn file_message(&self, src: &str, dest: &str) -> String {
+        if !self.theme.compact {
+            return format!("{} -> {}", src, dest);
+        }
+
+        let terminal_width = console::Term::stderr()
+            .size_checked()
+            .map(|(_, cols)| cols as usize)
+            .unwrap_or(DEFAULT_TERMINAL_WIDTH);
+
+        let path_width = terminal_width
+            .saturating_sub(COMPACT_RESERVED_WIDTH)
+            .max(COMPACT_MIN_WIDTH)
+            / 2;
+
+        format!(
+            "{} -> {}",
+            truncate_middle(src, path_width),
+            truncate_middle(dest, path_width)
+        )
+    }
+
+    /// print a line in plain mode, nothing is printed otherwise
+    pub fn log(&self, line: impl Display) {
+        if self.plain {
+            eprintln!("{}", line);
+        }
+    }
+
+    /// add a bar for a file if there is room for it, otherwise it's hidden
+    fn add_bar(&self, bar: ProgressBar) -> FileBar {
+        let has_room = self
+            .visible_bars
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |visible| {
+                (visible < self.max_visible_bars).then_some(visible + 1)
+            })
+            .is_ok();
+
+        if !has_room {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+
+            return FileBar {
+                bar,
+                multi_progress: None,
+                visible_bars: self.visible_bars.clone(),
+            };
+        }
+
+        FileBar {
+            bar: self.multi_progress.add(bar),
+            multi_progress: Some(self.multi_progress.clone()),
+            visible_bars: self.visible_bars.clone(),
+        }
+    }
+
+    /// add files which are going to be processed to the job totals
+    pub fn add_work(&self, files: u64, bytes: u64) {
+        self.files_total.fetch_add(files, Ordering::Relaxed);
+        self.job_bar.inc_length(bytes);
+        self.update_message();
+    }
+
+    pub fn inc_bytes(&self, bytes: u64) {
+        self.job_bar.inc(bytes);
+    }
+
+    pub fn file_done(&self) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+        self.update_message();
+    }
+
+    /// count files as done without copying them
+    pub fn skip_files(&self, files: u64, bytes: u64) {
+        self.files_done.fetch_add(files, Ordering::Relaxed);
+        self.job_bar.inc(bytes);
+        self.update_message();
+    }
+
+    pub fn finish(&self) {
+        self.job_bar.finish();
+
+        if self.plain {
+            eprintln!(
+                "{}",
+                plain_status(&self.job_bar, &self.files_done, &self.files_total)
+            );
+        }
+    }
+
+    pub fn clear(&self) -> std::io::Result<()> {
+        self.multi_progress.clear()
+    }
+
+    fn spawn_plain_reporter(&self, interval: Duration) {
+        let job_bar = self.job_bar.clone();
+        let files_done = self.files_done.clone();
+        let files_total = self.files_total.clone();
+
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+
+            if job_bar.is_finished() {
+                return;
+            }
+
+            eprintln!("{}", plain_status(&job_bar, &files_done, &files_total));
+        });
+    }
+
+    /// mark the job bar while the job is paused, which also covers workers waiting
+    /// to start their next file. the eta is reset on resuming, so the time spent
+    /// paused doesn't skew it
+    fn spawn_pause_watcher(&self) {
+        let job_bar = self.job_bar.clone();
+        let files_done = self.files_done.clone();
+        let files_total = self.files_total.clone();
+        let plain = self.plain;
+
+        std::thread::spawn(move || {
+            let mut was_paused = false;
+
+            while !job_bar.is_finished() {
+                std::thread::sleep(signals::PAUSE_POLL_INTERVAL);
+
+                let paused = signals::paused();
+
+                if paused == was_paused {
+                    continue;
+                }
+
+                was_paused = paused;
+                job_bar.set_message(job_message(&files_done, &files_total));
+
+                if !paused {
+                    job_bar.reset_eta();
+                }
+
+                if plain {
+                    eprintln!("{}", if paused { "paused" } else { "resumed" });
+                }
+            }
+        });
+    }
+
+    fn update_message(&self) {
+        self.job_bar
+            .set_message(job_message(&self.files_done, &self.files_total));
+    }
+}
+
+fn job_message(files_done: &AtomicU64, files_total: &AtomicU64) -> String {
+    let message = format!(
+        "{}/{} files",
+        files_done.load(Ordering::Relaxed),
+        files_total.load(Ordering::Relaxed)
+    );
+
+    if signals::paused() {
+        format!("[paused] {}", message)
+    } else {
+        message
+    }
+}
+
+impl Theme {
+    pub fn from_cli(cli: &Cli) -> Self {
+        Theme {
+            progress_template: cli
+                .bar_template
+                .clone()
+                .unwrap_or_else(|| DEFAULT_PROGRESS_TEMPLATE.to_string()),
+            verify_template: cli
+                .verify_template
+                .clone()
+                .unwrap_or_else(|| DEFAULT_VERIFY_TEMPLATE.to_string()),
+            compact: cli.compact,
+        }
+    }
+}
+
+impl Deref for FileBar {
+    type Target = ProgressBar;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bar
+    }
+}
+
+impl Drop for FileBar {
+    fn drop(&mut self) {
+        if let Some(multi_progress) = &self.multi_progress {
+            multi_progress.remove(&self.bar);
+            self.visible_bars.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn plain_status(job_bar: &ProgressBar, files_done: &AtomicU64, files_total: &AtomicU64) -> String {
+    let total_bytes = job_bar.length().unwrap_or_default();
+
+    let percent = match total_bytes {
+        0 => 100,
+        total => job_bar.position() * 100 / total,
+    };
+
+    format!(
+        "{:3}% {}/{} files {}/{} {}/s (eta {})",
+        percent,
+        files_done.load(Ordering::Relaxed),
+        files_total.load(Ordering::Relaxed),
+        HumanBytes(job_bar.position()),
+        HumanBytes(total_bytes),
+        HumanBytes(job_bar.per_sec() as u64),
+        HumanDuration(job_bar.eta())
+    )
+}
+
+pub fn create_job_bar(total_size: u64) -> Result<ProgressBar, TemplateError> {
     let progress_bar = ProgressBar::new(total_size);
 
     progress_bar.set_style(ProgressStyle::default_bar().template(
-        "{percent:3}% [{bar:.cyan/blue}] {msg} {bytes_per_sec:.green} {bytes:.yellow}/{total_bytes:.magenta} ({eta:.cyan})",
+        "{percent:3}% [{bar:.green/blue}] total {msg} {bytes_per_sec:.green} {bytes:.yellow}/{total_bytes:.magenta} ({eta:.cyan})",
     )?);
 
     Ok(progress_bar)
 }
 
-pub fn create_verify_bar(total_size: u64) -> Result<ProgressBar, TemplateError> {
+pub fn create_scan_spinner() -> Result<ProgressBar, TemplateError> {
+    let progress_bar = ProgressBar::new_spinner();
+
+    progress_bar
+        .set_style(ProgressStyle::default_spinner().template("{spinner:1} {msg} {pos} files")?);
+
+    progress_bar.enable_steady_tick(Duration::from_millis(100));
+
+    Ok(progress_bar)
+}
+
+pub fn create_progress_bar(total_size: u64, template: &str) -> Result<ProgressBar, TemplateError> {
     let progress_bar = ProgressBar::new(total_size);
 
-    progress_bar.set_style(
-        ProgressStyle::default_bar().template("{spinner:1} {percent:3}% verifying {msg}")?,
-    );
+    progress_bar.set_style(ProgressStyle::default_bar().template(template)?);
+
+    Ok(progress_bar)
+}
+
+pub fn create_verify_bar(total_size: u64, template: &str) -> Result<ProgressBar, TemplateError> {
+    let progress_bar = ProgressBar::new(total_size);
+
+    progress_bar.set_style(ProgressStyle::default_bar().template(template)?);
 
     progress_bar.enable_steady_tick(Duration::from_millis(100));
 
     Ok(progress_bar)
 }
+
+/// shorten a string to at most `max_width` chars by replacing its middle with `…`
+fn truncate_middle(s: &str, max_width: usize) -> String {
+    let len = s.chars().count();
+
+    if len <= max_width {
+        return s.to_string();
+    }
+
+    if max_width == 0 {
+        return String::new();
+    }
+
+    let keep = max_width - 1;
+    let head = keep.div_ceil(2);
+    let tail = keep / 2;
+
+    let mut truncated = s.chars().take(head).collect::<String>();
+    truncated.push('…');
+    truncated.extend(s.chars().skip(len - tail));
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_middle() {
+        assert_eq!(truncate_middle("short", 10), "short");
+        assert_eq!(truncate_middle("some/long/path/file", 9), "some…file");
+        assert_eq!(truncate_middle("some/long/path/file", 8), "some…ile");
+        assert_eq!(truncate_middle("æøå/æøå", 5), "æø…øå");
+    }
+}
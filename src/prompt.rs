@@ -0,0 +1,178 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Error, ErrorKind},
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+
+use indicatif::{HumanBytes, MultiProgress, ProgressDrawTarget};
+
+use crate::signals;
+
+#[cfg(unix)]
+const TERMINAL: &str = "/dev/tty";
+
+#[cfg(windows)]
+const TERMINAL: &str = "CONIN$";
+
+static PROMPT: OnceLock<Mutex<Prompt>> = OnceLock::new();
+
+/// what to do with a destination file which exists, for --overwrite interactive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Answer {
+    Overwrite,
+    Skip,
+    /// save the incoming file under a name from --keep-both-pattern
+    Rename,
+}
+
+struct Prompt {
+    /// read directly, since stdin can hold the job input
+    terminal: Box<dyn BufRead + Send>,
+    multi_progress: MultiProgress,
+    /// an answer given for all remaining files
+    all: Option<Answer>,
+}
+
+/// open the terminal the questions are answered in
+pub fn init(multi_progress: MultiProgress) -> std::io::Result<()> {
+    let terminal = File::open(TERMINAL).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("--overwrite interactive needs a terminal: {}", e),
+        )
+    })?;
+
+    init_with(Box::new(BufReader::new(terminal)), multi_progress);
+
+    Ok(())
+}
+
+/// read the answers from another input than the terminal
+pub fn init_with(terminal: Box<dyn BufRead + Send>, multi_progress: MultiProgress) {
+    _ = PROMPT.set(Mutex::new(Prompt {
+        terminal,
+        multi_progress,
+        all: None,
+    }));
+}
+
+/// ask what to do with a destination which exists. only one worker asks at a time,
+/// the progress bars are hidden until the question is answered while the other
+/// workers keep copying
+pub fn ask(src: &Path, destination: &Path) -> std::io::Result<Answer> {
+    let prompt = PROMPT
+        .get()
+        .ok_or_else(|| Error::other("Prompt is not initialized"))?;
+
+    let mut prompt = prompt.lock().expect("Failed to lock prompt");
+
+    if let Some(answer) = prompt.all {
+        return Ok(answer);
+    }
+
+    let Prompt {
+        terminal,
+        multi_progress,
+        all,
+    } = &mut *prompt;
+
+    // waiting for the answer inside `suspend` would block every bar update meanwhile
+    let hide_bars = !multi_progress.is_hidden();
+
+    if hide_bars {
+        multi_progress.clear()?;
+        multi_progress.set_draw_target(ProgressDrawTarget::hidden());
+    }
+
+    let answer = read_answer(terminal, src, destination);
+
+    if hide_bars {
+        multi_progress.set_draw_target(ProgressDrawTarget::stderr());
+    }
+
+    let (answer, for_all) = answer?;
+
+    if for_all {
+        *all = Some(answer);
+    }
+
+    Ok(answer)
+}
+
+/// ask until a valid answer is given. returns whether it applies to all remaining files
+fn read_answer(
+    terminal: &mut impl BufRead,
+    src: &Path,
+    destination: &Path,
+) -> std::io::Result<(Answer, bool)> {
+    loop {
+        eprint!(
+            "{} exists. [o]verwrite, [s]kip, [r]ename, [d]ifferences (capital letter for all): ",
+            destination.display()
+        );
+
+        let mut line = String::new();
+
+        if terminal.read_line(&mut line)? == 0 {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "No answer given"));
+        }
+
+        if signals::interrupted() {
+            return Err(Error::new(ErrorKind::Interrupted, "Interrupted by signal"));
+        }
+
+        let line = line.trim();
+
+        let answer = match line.to_ascii_lowercase().as_str() {
+            "o" => Answer::Overwrite,
+            "s" => Answer::Skip,
+            "r" => Answer::Rename,
+            "d" => {
+                print_differences(src, destination);
+                continue;
+            }
+            _ => continue,
+        };
+
+        return Ok((answer, line.chars().all(|c| c.is_ascii_uppercase())));
+    }
+}
+
+/// sizes and modification times of both files
+fn print_differences(src: &Path, destination: &Path) {
+    for (label, path) in [("source", src), ("destination", destination)] {
+        let Ok(metadata) = fs::metadata(path) else {
+            continue;
+        };
+
+        let modified = metadata
+            .modified()
+            .map(|time| humantime::format_rfc3339_seconds(time).to_string())
+            .unwrap_or_default();
+
+        eprintln!(
+            "  {:<12} {:>10}  {}",
+            label,
+            HumanBytes(metadata.len()).to_string(),
+            modified
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_answer() {
+        let read =
+            |input: &str| read_answer(&mut input.as_bytes(), Path::new("src"), Path::new("dest"));
+
+        assert_eq!(read("o\n").unwrap(), (Answer::Overwrite, false));
+        assert_eq!(read("  S \n").unwrap(), (Answer::Skip, true));
+        // invalid answers and differences ask again
+        assert_eq!(read("x\nd\nr\n").unwrap(), (Answer::Rename, false));
+        assert_eq!(read("x\n").unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+}
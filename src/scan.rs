@@ -0,0 +1,224 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use walkdir::DirEntry;
+
+use crate::{
+    cli::ConflictPolicy,
+    events::{self, Event, SkipReason},
+    filter::Filter,
+    progress_bar::JobProgress,
+};
+
+/// the files of one source and the destinations they are copied to
+pub struct Scan {
+    pub source: PathBuf,
+    pub destinations: Vec<PathBuf>,
+    /// all files found, including ones not selected by the size, age and type filters
+    pub walked: Vec<DirEntry>,
+    /// the files to copy
+    pub files: Vec<DirEntry>,
+}
+
+impl Scan {
+    /// walk the source, reporting ignored files and skipped mount points
+    pub fn new(
+        source: PathBuf,
+        destinations: Vec<PathBuf>,
+        filter: &Filter,
+        job_progress: &JobProgress,
+    ) -> Self {
+        let scan_spinner = job_progress.scan_spinner(&source);
+
+        let walked = filter
+            .walk(&source)
+            .into_iter()
+            .filter_entry(|e| {
+                if !filter.allows_entry(&source, e) {
+                    return false;
+                }
+
+                if filter.is_other_filesystem(&source, &source, e) {
+                    events::emit(Event::SkipMount { path: e.path() });
+                    return false;
+                }
+
+                if filter.ignores_entry(&source, &source, e) {
+                    events::emit(Event::Ignore { path: e.path() });
+                    return false;
+                }
+
+                true
+            })
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    // an unreadable directory or file fails the job partially, like a failed copy
+                    eprintln!("Error reading source: {}", e);
+
+                    events::emit(Event::Error {
+                        src: e.path(),
+                        dest: None,
+                        message: e.to_string(),
+                    });
+
+                    None
+                }
+            })
+            .filter(|e| e.path().is_file())
+            .inspect(|_| scan_spinner.inc(1))
+            .collect::<Vec<_>>();
+
+        let files = walked
+            .iter()
+            .filter(|e| filter.selects(e))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // reported as the files found, without the ones the attributes don't select
+        scan_spinner.set_position(files.len() as u64);
+        job_progress.finish_scan(scan_spinner);
+
+        Self {
+            source,
+            destinations,
+            walked,
+            files,
+        }
+    }
+
+    /// where a file of this source ends up in a destination
+    pub fn target(&self, destination: &Path, file: &DirEntry) -> Option<PathBuf> {
+        let relative_path = file.path().strip_prefix(&self.source).ok()?;
+
+        if relative_path == Path::new("") {
+            Some(destination.to_path_buf())
+        } else {
+            Some(destination.join(relative_path))
+        }
+    }
+
+    /// the directory holding the lock and .pcp/ state of a destination.
+    /// a single file keeps them in the directory it's copied into
+    pub fn state_root<'a>(&self, destination: &'a Path) -> &'a Path {
+        match destination.parent() {
+            Some(parent) if self.source.is_file() => parent,
+            _ => destination,
+        }
+    }
+
+    /// all paths this source provides in its destinations.
+    /// files which aren't copied because of the filters still count
+    pub fn targets(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.destinations.iter().flat_map(move |destination| {
+            self.walked
+                .iter()
+                .filter_map(move |file| self.target(destination, file))
+        })
+    }
+}
+
+/// fail the job before scanning anything if a source doesn't exist, like cp
+pub fn check_sources<'a>(sources: impl IntoIterator<Item = &'a Path>) -> std::io::Result<()> {
+    for source in sources {
+        if let Err(e) = source.symlink_metadata() {
+            return Err(Error::new(
+                e.kind(),
+                format!("Cannot read {}: {}", source.display(), e),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// find destination files provided by more than one source and apply the conflict policy.
+/// sources are ranked in the order they were passed
+pub fn resolve_conflicts(scans: &mut [Scan], policy: &ConflictPolicy) -> std::io::Result<()> {
+    if scans.len() < 2 {
+        return Ok(());
+    }
+
+    let mut order = (0..scans.len()).collect::<Vec<_>>();
+
+    if *policy == ConflictPolicy::Last {
+        order.reverse();
+    }
+
+    let mut owners = HashMap::<PathBuf, usize>::new();
+    let mut losers = vec![HashSet::<PathBuf>::new(); scans.len()];
+    let mut conflicts = 0;
+
+    for index in order {
+        let scan = &scans[index];
+
+        for destination in &scan.destinations {
+            for file in &scan.files {
+                let Some(target) = scan.target(destination, file) else {
+                    continue;
+                };
+
+                match owners.get(&target) {
+                    Some(&owner) if owner != index => {
+                        conflicts += 1;
+
+                        if *policy == ConflictPolicy::Error {
+                            eprintln!(
+                                "Conflict: {} is provided by {} and {}",
+                                target.display(),
+                                scans[owner].source.display(),
+                                scan.source.display()
+                            );
+                        }
+
+                        losers[index].insert(file.path().to_path_buf());
+                    }
+                    Some(_) => {}
+                    None => {
+                        owners.insert(target, index);
+                    }
+                }
+            }
+        }
+    }
+
+    if conflicts == 0 {
+        return Ok(());
+    }
+
+    if *policy == ConflictPolicy::Error {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!(
+                "{} file(s) are provided by more than one source, see --on-conflict",
+                conflicts
+            ),
+        ));
+    }
+
+    for (scan, losers) in scans.iter_mut().zip(losers) {
+        let (skipped, files): (Vec<_>, Vec<_>) = scan
+            .files
+            .drain(..)
+            .partition(|file| losers.contains(file.path()));
+
+        scan.files = files;
+
+        for file in &skipped {
+            for destination in &scan.destinations {
+                if let Some(target) = scan.target(destination, file) {
+                    events::emit(Event::Skip {
+                        src: file.path(),
+                        dest: &target,
+                        reason: SkipReason::Conflict,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
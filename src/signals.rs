@@ -0,0 +1,149 @@
+use std::{
+    io::{BufRead, IsTerminal},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use indicatif::ProgressBar;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    flag,
+};
+
+/// exit code used when the job was stopped gracefully by SIGINT/SIGTERM
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// exit code used when a second signal forces pcp to quit immediately
+pub const FORCE_QUIT_EXIT_CODE: i32 = 137;
+
+const RUNNING: usize = 0;
+const PAUSED: usize = 1;
+pub const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+static PAUSE_STATE: OnceLock<Arc<AtomicUsize>> = OnceLock::new();
+static REMAINING: Mutex<Vec<(PathBuf, PathBuf)>> = Mutex::new(vec![]);
+
+/// install handlers for SIGINT and SIGTERM.
+/// the first signal only sets the interrupted flag so ongoing work can checkpoint,
+/// a second signal terminates the process right away.
+pub fn install() -> std::io::Result<()> {
+    let interrupted = INTERRUPTED.get_or_init(|| Arc::new(AtomicBool::new(false)));
+
+    for signal in [SIGINT, SIGTERM] {
+        // the conditional shutdown must be registered first so it only fires
+        // when the flag was already set by a previous signal
+        flag::register_conditional_shutdown(signal, FORCE_QUIT_EXIT_CODE, interrupted.clone())?;
+        flag::register(signal, interrupted.clone())?;
+    }
+
+    let pause_state = PAUSE_STATE.get_or_init(|| Arc::new(AtomicUsize::new(RUNNING)));
+
+    #[cfg(unix)]
+    {
+        use signal_hook::consts::{SIGUSR1, SIGUSR2};
+
+        flag::register_usize(SIGUSR1, pause_state.clone(), PAUSED)?;
+        flag::register_usize(SIGUSR2, pause_state.clone(), RUNNING)?;
+    }
+
+    Ok(())
+}
+
+/// toggle pausing when `p` followed by enter is typed into the terminal.
+/// only listens when stdin is a terminal, since otherwise it holds the job input.
+pub fn listen_for_pause_key() {
+    let stdin = std::io::stdin();
+
+    if !stdin.is_terminal() {
+        return;
+    }
+
+    std::thread::spawn(move || {
+        for line in stdin.lock().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+
+            if line.trim().eq_ignore_ascii_case("p") {
+                toggle_pause();
+            }
+        }
+    });
+}
+
+fn toggle_pause() {
+    let Some(pause_state) = PAUSE_STATE.get() else {
+        return;
+    };
+
+    let next = match pause_state.load(Ordering::Relaxed) {
+        PAUSED => RUNNING,
+        _ => PAUSED,
+    };
+
+    pause_state.store(next, Ordering::Relaxed);
+}
+
+pub fn paused() -> bool {
+    PAUSE_STATE
+        .get()
+        .is_some_and(|state| state.load(Ordering::Relaxed) == PAUSED)
+}
+
+/// block the calling worker while the job is paused.
+/// the progress bar is marked as paused and its eta is reset once the job resumes,
+/// so the time spent paused doesn't skew the estimate.
+/// returns early if the job gets interrupted while paused.
+pub fn wait_while_paused(progress_bar: Option<&ProgressBar>) {
+    if !paused() {
+        return;
+    }
+
+    let message = progress_bar.map(|bar| {
+        let message = bar.message();
+        bar.set_message(format!("[paused] {}", message));
+        message
+    });
+
+    while paused() && !interrupted() {
+        std::thread::sleep(PAUSE_POLL_INTERVAL);
+    }
+
+    if let (Some(bar), Some(message)) = (progress_bar, message) {
+        bar.set_message(message);
+        bar.reset_eta();
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED
+        .get()
+        .is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+/// record a source/destination pair which was not (fully) copied because of an interrupt
+pub fn mark_remaining(src: impl AsRef<Path>, dest: impl AsRef<Path>) {
+    REMAINING
+        .lock()
+        .expect("Failed to lock remaining")
+        .push((src.as_ref().to_path_buf(), dest.as_ref().to_path_buf()));
+}
+
+/// print everything which remains to be copied and exit with [INTERRUPTED_EXIT_CODE]
+pub fn exit_interrupted() -> ! {
+    let mut remaining = REMAINING.lock().expect("Failed to lock remaining");
+    remaining.sort();
+
+    eprintln!("Interrupted. {} item(s) remaining:", remaining.len());
+
+    for (src, dest) in remaining.iter() {
+        eprintln!("  {} -> {}", src.display(), dest.display());
+    }
+
+    std::process::exit(INTERRUPTED_EXIT_CODE);
+}
@@ -0,0 +1,131 @@
+use std::{path::PathBuf, time::Duration};
+
+use indicatif::{HumanBytes, HumanDuration};
+
+use crate::events::{PlanSummary, Summary, Tally};
+
+/// every file was copied, skipped or deleted as requested
+pub const SUCCESS_EXIT_CODE: i32 = 0;
+
+/// the job could not run at all, for example because of invalid arguments
+pub const FATAL_EXIT_CODE: i32 = 1;
+
+/// some files could not be read, copied or deleted
+pub const PARTIAL_FAILURE_EXIT_CODE: i32 = 2;
+
+/// some files failed verification, even after retrying
+pub const VERIFICATION_FAILED_EXIT_CODE: i32 = 3;
+
+/// print an error parsing the arguments and exit. clap exits usage errors with 2,
+/// which would look like a partial failure
+pub fn exit_usage_error(e: clap::Error) -> ! {
+    let _ = e.print();
+
+    if e.exit_code() == SUCCESS_EXIT_CODE {
+        // --help and --version
+        std::process::exit(SUCCESS_EXIT_CODE);
+    }
+
+    std::process::exit(FATAL_EXIT_CODE);
+}
+
+pub fn exit_code(summary: &Summary) -> i32 {
+    if summary.unresolved_verify_failures > 0 {
+        VERIFICATION_FAILED_EXIT_CODE
+    } else if summary.total.failed > 0 {
+        PARTIAL_FAILURE_EXIT_CODE
+    } else {
+        SUCCESS_EXIT_CODE
+    }
+}
+
+pub fn print(summary: &Summary) {
+    eprintln!(
+        "{} in {} ({}/s)",
+        describe(&summary.total),
+        HumanDuration(Duration::from_millis(summary.elapsed_ms)),
+        HumanBytes(summary.bytes_per_sec)
+    );
+
+    if summary.unresolved_verify_failures > 0 {
+        eprintln!(
+            "  {} file(s) failed verification",
+            summary.unresolved_verify_failures
+        );
+    }
+
+    if summary.ignored > 0 {
+        eprintln!("  {} path(s) ignored by ignore files", summary.ignored);
+    }
+
+    print_skipped_mounts(&summary.skipped_mounts);
+
+    if summary.destinations.len() < 2 {
+        return;
+    }
+
+    for destination in &summary.destinations {
+        eprintln!(
+            "  {}: {}",
+            destination.path.display(),
+            describe(&destination.tally)
+        );
+    }
+}
+
+pub fn print_plan(plan: &PlanSummary) {
+    eprintln!(
+        "dry run: would copy {}, overwrite {}, keep both {}, ask about {}, skip {}, update metadata of {}, delete {}, back up {}, rename {} ({} to write)",
+        plan.copy,
+        plan.overwrite,
+        plan.keep_both,
+        plan.ask,
+        plan.skip,
+        plan.metadata_update,
+        plan.delete,
+        plan.backup,
+        plan.rename,
+        HumanBytes(plan.bytes)
+    );
+
+    if plan.ignore > 0 {
+        eprintln!("  {} path(s) ignored by ignore files", plan.ignore);
+    }
+
+    print_skipped_mounts(&plan.skipped_mounts);
+}
+
+fn print_skipped_mounts(skipped_mounts: &[PathBuf]) {
+    for path in skipped_mounts {
+        eprintln!("  skipped mount point {}", path.display());
+    }
+}
+
+fn describe(tally: &Tally) -> String {
+    let mut parts = vec![format!(
+        "copied {} ({})",
+        tally.copied,
+        HumanBytes(tally.bytes)
+    )];
+
+    let counts = [
+        ("kept both", tally.kept_both),
+        ("skipped", tally.skipped),
+        ("already completed", tally.already_completed),
+        ("metadata updated", tally.metadata_updated),
+        ("failed", tally.failed),
+        ("verified", tally.verified),
+        ("verification failed", tally.verify_failed),
+        ("retried", tally.retried),
+        ("purged", tally.deleted),
+        ("backed up", tally.backed_up),
+    ];
+
+    for (label, count) in counts {
+        if count > 0 {
+            parts.push(format!("{} {}", label, count));
+        }
+    }
+
+    parts.join(", ")
+}